@@ -0,0 +1,190 @@
+use crate::dom;
+use htmd::Node;
+
+/// Formatting that Markdown can express, resolved from the styles of an element
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct TextStyle {
+    pub(crate) italic: bool,
+    pub(crate) bold: bool,
+    pub(crate) strikethrough: bool,
+    pub(crate) monospace: bool,
+    pub(crate) centered: bool,
+}
+
+/// Rules of the book's stylesheets with simple selectors like `span.italic`,
+/// which is what Calibre and InDesign generate. Rules with combinators or
+/// pseudo-classes are ignored.
+#[derive(Debug, Default)]
+pub(crate) struct Stylesheet {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    tag: Option<String>,
+    classes: Vec<String>,
+    declarations: Vec<(String, String)>,
+}
+
+impl Rule {
+    fn specificity(&self) -> (usize, usize) {
+        (self.classes.len(), usize::from(self.tag.is_some()))
+    }
+
+    fn matches(&self, tag: &str, classes: &[String]) -> bool {
+        self.tag.as_deref().is_none_or(|rule_tag| rule_tag == tag)
+            && self.classes.iter().all(|class| classes.contains(class))
+    }
+}
+
+impl Stylesheet {
+    /// Add the rules of a stylesheet, after those added before
+    pub(crate) fn add(&mut self, css: &str) {
+        parse_rules(&strip_comments(css), &mut self.rules);
+    }
+
+    /// Style of an element from the matching rules and its `style` attribute
+    pub(crate) fn style(&self, node: &Node) -> TextStyle {
+        let Some(tag) = dom::element_name(node) else {
+            return TextStyle::default();
+        };
+        let classes = dom::attr(node, "class")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let mut rules = self
+            .rules
+            .iter()
+            .filter(|rule| rule.matches(tag, &classes))
+            .collect::<Vec<_>>();
+        // Stable, so rules of the same specificity keep their order
+        rules.sort_by_key(|rule| rule.specificity());
+        let mut style = TextStyle::default();
+        for rule in rules {
+            for (property, value) in &rule.declarations {
+                apply(&mut style, property, value);
+            }
+        }
+        let inline = dom::attr(node, "style").unwrap_or_default();
+        for (property, value) in parse_declarations(&inline) {
+            apply(&mut style, &property, &value);
+        }
+        style
+    }
+}
+
+fn apply(style: &mut TextStyle, property: &str, value: &str) {
+    match property {
+        "font-style" => style.italic = matches!(value, "italic" | "oblique"),
+        "font-weight" => {
+            style.bold = match value {
+                "bold" | "bolder" => true,
+                _ => value.parse::<u32>().is_ok_and(|weight| weight >= 600),
+            }
+        }
+        "text-decoration" | "text-decoration-line" => {
+            style.strikethrough = value.contains("line-through")
+        }
+        "font-family" => {
+            style.monospace = ["monospace", "courier", "consolas", "menlo", "monaco"]
+                .iter()
+                .any(|family| value.contains(family))
+        }
+        "text-align" => style.centered = value == "center",
+        _ => {}
+    }
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut stripped = String::new();
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+fn parse_rules(css: &str, rules: &mut Vec<Rule>) {
+    let mut rest = css;
+    while let Some(open) = rest.find('{') {
+        // Statements like `@import url(a.css);` end before the selector
+        let prelude = rest[..open].rsplit(';').next().unwrap_or_default().trim();
+        let Some(close) = matching_brace(&rest[open..]).map(|close| open + close) else {
+            break;
+        };
+        let block = &rest[open + 1..close];
+        if prelude.starts_with('@') {
+            // The rules of `@media` and `@supports` blocks apply as well
+            parse_rules(block, rules);
+        } else {
+            let declarations = parse_declarations(block);
+            for selector in prelude.split(',') {
+                if let Some((tag, classes)) = parse_selector(selector.trim()) {
+                    rules.push(Rule {
+                        tag,
+                        classes,
+                        declarations: declarations.clone(),
+                    });
+                }
+            }
+        }
+        rest = &rest[close + 1..];
+    }
+}
+
+/// Byte offset of the `}` closing the block that `css` starts with
+fn matching_brace(css: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in css.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// `tag`, `.class` or `tag.class1.class2`, `None` for anything more complex
+fn parse_selector(selector: &str) -> Option<(Option<String>, Vec<String>)> {
+    let mut parts = selector.split('.');
+    let tag = parts.next()?.to_lowercase();
+    let classes = parts.map(str::to_string).collect::<Vec<_>>();
+    let is_name = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    if (!tag.is_empty() && !is_name(&tag)) || !classes.iter().all(|class| is_name(class)) {
+        return None;
+    }
+    if tag.is_empty() && classes.is_empty() {
+        return None;
+    }
+    Some(((!tag.is_empty()).then_some(tag), classes))
+}
+
+/// `property: value` pairs of a declaration block, lowercased and without `!important`
+fn parse_declarations(block: &str) -> Vec<(String, String)> {
+    block
+        .split(';')
+        .filter_map(|declaration| declaration.split_once(':'))
+        .map(|(property, value)| {
+            let value = value.trim().to_lowercase();
+            let value = value.trim_end_matches("!important").trim();
+            (property.trim().to_lowercase(), value.to_string())
+        })
+        .collect()
+}
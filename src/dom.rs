@@ -0,0 +1,228 @@
+use htmd::Node;
+use markup5ever_rcdom::NodeData;
+use std::rc::Rc;
+
+/// Local name of an element node, e.g. `h1`, `None` for non-element nodes
+pub(crate) fn element_name(node: &Node) -> Option<&str> {
+    match &node.data {
+        NodeData::Element { name, .. } => Some(&name.local),
+        _ => None,
+    }
+}
+
+/// Concatenated text of a node and all its descendants, with whitespace collapsed
+pub(crate) fn text_content(node: &Node) -> String {
+    fn collect(node: &Node, text: &mut String) {
+        if let NodeData::Text { contents } = &node.data {
+            text.push_str(&contents.borrow());
+        }
+        for child in node.children.borrow().iter() {
+            collect(child, text);
+        }
+    }
+    let mut text = String::new();
+    collect(node, &mut text);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// First node in document order (including `node` itself) matching `predicate`
+pub(crate) fn find_first(
+    node: &Rc<Node>,
+    predicate: &impl Fn(&Rc<Node>) -> bool,
+) -> Option<Rc<Node>> {
+    if predicate(node) {
+        return Some(node.clone());
+    }
+    node.children
+        .borrow()
+        .iter()
+        .find_map(|child| find_first(child, predicate))
+}
+
+/// All descendants of a node in document order, excluding the node itself
+pub(crate) fn descendants(node: &Rc<Node>) -> Vec<Rc<Node>> {
+    let mut nodes = Vec::new();
+    for child in node.children.borrow().iter() {
+        nodes.push(child.clone());
+        nodes.extend(descendants(child));
+    }
+    nodes
+}
+
+/// Parent of a node, `None` for the document itself
+pub(crate) fn parent(node: &Node) -> Option<Rc<Node>> {
+    let weak = node.parent.take();
+    let parent = weak.as_ref().and_then(|weak| weak.upgrade());
+    node.parent.set(weak);
+    parent
+}
+
+/// Value of the attribute `name` on an element node
+pub(crate) fn attr(node: &Node, name: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|attr| &*attr.name.local == name)
+            .map(|attr| attr.value.to_string()),
+        _ => None,
+    }
+}
+
+/// Id a link can point to: `id`, `xml:id` or the `name` of a legacy `<a name>`
+pub(crate) fn anchor_id(node: &Node) -> Option<String> {
+    let mut ids = vec![attr(node, "id"), attr(node, "xml:id")];
+    if element_name(node) == Some("a") {
+        ids.push(attr(node, "name"));
+    }
+    ids.into_iter()
+        .flatten()
+        .map(|id| id.trim().to_string())
+        .find(|id| !id.is_empty())
+}
+
+/// Replace the value of the attributes `names` in a node and all its descendants
+/// with what `rewrite` returns, keeping those it returns `None` for
+pub(crate) fn rewrite_attrs(
+    node: &Rc<Node>,
+    names: &[&str],
+    rewrite: &impl Fn(&str) -> Option<String>,
+) {
+    for node in std::iter::once(node.clone()).chain(descendants(node)) {
+        if let NodeData::Element { attrs, .. } = &node.data {
+            for attr in attrs.borrow_mut().iter_mut() {
+                if names.contains(&&*attr.name.local)
+                    && let Some(value) = rewrite(&attr.value)
+                {
+                    attr.value = value.into();
+                }
+            }
+        }
+    }
+}
+
+/// Set the value of the existing attribute `name` of an element node
+pub(crate) fn set_attr(node: &Node, name: &str, value: &str) {
+    if let NodeData::Element { attrs, .. } = &node.data {
+        for attr in attrs.borrow_mut().iter_mut() {
+            if &*attr.name.local == name {
+                attr.value = value.into();
+            }
+        }
+    }
+}
+
+/// Remove the attribute `name` from an element node
+pub(crate) fn remove_attr(node: &Node, name: &str) {
+    if let NodeData::Element { attrs, .. } = &node.data {
+        attrs.borrow_mut().retain(|attr| &*attr.name.local != name);
+    }
+}
+
+/// Element children of a node with one of the given names
+pub(crate) fn child_elements(node: &Node, names: &[&str]) -> Vec<Rc<Node>> {
+    node.children
+        .borrow()
+        .iter()
+        .filter(|child| has_name_in(child, names))
+        .cloned()
+        .collect()
+}
+
+fn has_name_in(node: &Node, names: &[&str]) -> bool {
+    element_name(node).is_some_and(|name| names.contains(&name))
+}
+
+/// Serialize a node back to HTML on a single line, collapsing whitespace and keeping
+/// only the attributes `keep_attr` accepts by name and value. Whitespace inside
+/// `<pre>` and `<textarea>` is kept, with line breaks as `&#10;` so a blank line
+/// can't end the Markdown HTML block.
+pub(crate) fn to_html(node: &Node, keep_attr: &impl Fn(&str, &str) -> bool) -> String {
+    let mut html = String::new();
+    write_html(node, keep_attr, false, &mut html);
+    html
+}
+
+fn write_html(
+    node: &Node,
+    keep_attr: &impl Fn(&str, &str) -> bool,
+    preformatted: bool,
+    html: &mut String,
+) {
+    match &node.data {
+        NodeData::Text { contents } => {
+            let text = contents.borrow();
+            let mut last_was_space = html.ends_with(' ');
+            for c in text.chars() {
+                if preformatted && c == '\n' {
+                    html.push_str("&#10;");
+                    continue;
+                }
+                if c.is_whitespace() && !preformatted {
+                    if !last_was_space {
+                        html.push(' ');
+                    }
+                    last_was_space = true;
+                    continue;
+                }
+                last_was_space = false;
+                match c {
+                    '&' => html.push_str("&amp;"),
+                    '<' => html.push_str("&lt;"),
+                    '>' => html.push_str("&gt;"),
+                    _ => html.push(c),
+                }
+            }
+        }
+        NodeData::Element { name, attrs, .. } => {
+            html.push('<');
+            html.push_str(&name.local);
+            for attr in attrs.borrow().iter() {
+                // Namespace declarations don't survive being parsed as HTML anyway.
+                let is_namespace =
+                    &*attr.name.local == "xmlns" || attr.name.prefix.as_deref() == Some("xmlns");
+                if !is_namespace && keep_attr(&attr.name.local, &attr.value) {
+                    html.push_str(&format!(
+                        " {}=\"{}\"",
+                        attr.name.local,
+                        crate::escape_attr(&attr.value)
+                    ));
+                }
+            }
+            html.push('>');
+            if is_void_element(&name.local) {
+                return;
+            }
+            let preformatted = preformatted || matches!(&*name.local, "pre" | "textarea");
+            for child in node.children.borrow().iter() {
+                write_html(child, keep_attr, preformatted, html);
+            }
+            html.push_str(&format!("</{}>", name.local));
+        }
+        NodeData::Document => {
+            for child in node.children.borrow().iter() {
+                write_html(child, keep_attr, preformatted, html);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_void_element(name: &str) -> bool {
+    matches!(
+        name,
+        "area"
+            | "base"
+            | "br"
+            | "col"
+            | "embed"
+            | "hr"
+            | "img"
+            | "input"
+            | "link"
+            | "meta"
+            | "source"
+            | "track"
+            | "wbr"
+    )
+}
@@ -0,0 +1,133 @@
+use crate::dom;
+use crate::options::AdmonitionStyle;
+use htmd::Element;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Callout classes recognized without any configuration, with their admonition kind
+const DEFAULT_CLASSES: &[(&str, &str)] = &[
+    ("caution", "caution"),
+    ("danger", "danger"),
+    ("example", "example"),
+    ("hint", "tip"),
+    ("important", "important"),
+    ("info", "info"),
+    ("note", "note"),
+    ("tip", "tip"),
+    ("warning", "warning"),
+];
+
+/// Converts callouts marked by a class or `data-type` to admonitions, recording
+/// when the book needs the `mdbook-admonish` preprocessor
+pub(crate) struct AdmonitionHandler {
+    style: AdmonitionStyle,
+    classes: HashMap<String, String>,
+    found_admonish: Arc<AtomicBool>,
+}
+
+impl AdmonitionHandler {
+    pub(crate) fn new(
+        style: AdmonitionStyle,
+        extra_classes: &HashMap<String, String>,
+        found_admonish: Arc<AtomicBool>,
+    ) -> Self {
+        let mut classes = DEFAULT_CLASSES
+            .iter()
+            .map(|(class, kind)| (class.to_string(), kind.to_string()))
+            .collect::<HashMap<_, _>>();
+        for (class, kind) in extra_classes {
+            classes.insert(class.to_lowercase(), kind.clone());
+        }
+        Self {
+            style,
+            classes,
+            found_admonish,
+        }
+    }
+
+    fn kind(&self, element: &Element) -> Option<&str> {
+        let class = dom::attr(element.node, "class").unwrap_or_default();
+        let data_type = dom::attr(element.node, "data-type").unwrap_or_default();
+        class
+            .split_whitespace()
+            .chain(data_type.split_whitespace())
+            .find_map(|class| self.classes.get(&class.to_lowercase()))
+            .map(String::as_str)
+    }
+}
+
+impl ElementHandler for AdmonitionHandler {
+    fn handle(&self, handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        let Some(kind) = self.kind(&element) else {
+            return handlers.fallback(element);
+        };
+        let content = handlers.walk_children(element.node).content;
+        let (title, content) = split_title(content.trim_matches('\n'), kind);
+        let admonition = match self.style {
+            AdmonitionStyle::Admonish => {
+                self.found_admonish.store(true, Ordering::Relaxed);
+                admonish_block(kind, title.as_deref(), &content)
+            }
+            AdmonitionStyle::GitHub => github_alert(kind, title.as_deref(), &content),
+        };
+        Some(format!("\n\n{admonition}\n\n").into())
+    }
+}
+
+/// Take a leading heading out of the content as the admonition title, dropping it
+/// when it just repeats the kind, e.g. "Note"
+fn split_title(content: &str, kind: &str) -> (Option<String>, String) {
+    let mut lines = content.lines().collect::<Vec<_>>();
+    let Some(idx) = lines
+        .iter()
+        .position(|line| !line.trim().is_empty() && !crate::is_html_anchor(line))
+    else {
+        return (None, content.to_string());
+    };
+    if !crate::is_atx_heading(lines[idx]) {
+        return (None, content.to_string());
+    }
+    let title = lines
+        .remove(idx)
+        .trim()
+        .trim_start_matches('#')
+        .trim()
+        .to_string();
+    let content = lines.join("\n").trim_matches('\n').to_string();
+    let title = Some(title).filter(|title| !title.is_empty() && !title.eq_ignore_ascii_case(kind));
+    (title, content)
+}
+
+fn admonish_block(kind: &str, title: Option<&str>, content: &str) -> String {
+    let fence = crate::backtick_fence(content, 3);
+    let title = match title {
+        Some(title) => format!(" title=\"{}\"", title.replace('"', "&quot;")),
+        None => String::new(),
+    };
+    format!("{fence}admonish {kind}{title}\n{content}\n{fence}")
+}
+
+fn github_alert(kind: &str, title: Option<&str>, content: &str) -> String {
+    let alert = match kind {
+        "tip" | "hint" | "success" | "example" => "TIP",
+        "important" => "IMPORTANT",
+        "warning" | "question" => "WARNING",
+        "caution" | "danger" | "failure" | "bug" | "error" => "CAUTION",
+        _ => "NOTE",
+    };
+    let mut lines = vec![format!("> [!{alert}]")];
+    if let Some(title) = title {
+        lines.push(format!("> **{title}**"));
+        lines.push(">".to_string());
+    }
+    for line in content.lines() {
+        if line.is_empty() {
+            lines.push(">".to_string());
+        } else {
+            lines.push(format!("> {line}"));
+        }
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,141 @@
+use crate::{dom, escape_attr};
+use htmd::Element;
+use htmd::Node;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use regex::Regex;
+use std::collections::HashSet;
+use std::sync::{Arc, LazyLock};
+
+/// Elements that get their anchor on a line of its own
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "main",
+    "mbp:pagebreak",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "ul",
+];
+
+/// Elements that get their anchor right in front of their content, or on a line of
+/// its own when they convert to a block like display math does
+const INLINE_TAGS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "cite", "code", "del", "dfn", "em", "i", "img", "ins", "kbd",
+    "mark", "math", "q", "ruby", "s", "samp", "small", "span", "strong", "sub", "sup", "time", "u",
+    "var",
+];
+
+/// Match the ids Calibre, InDesign and Mobi conversions sprinkle over a book
+static VENDOR_ID: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:calibre_link-|calibre_toc_|calibre_pb_|filepos|_idTextAnchor|_idIndexMarker|_idContainer|_idParaDest-)\d+$",
+    )
+    .expect("unreachable")
+});
+/// Match the page break markers of Calibre and Mobi conversions
+static PAGE_BREAK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:calibre_pb_\d+|mbp_pagebreak)$").expect("unreachable"));
+
+/// Which ids of a chapter get an `<a id>` anchor: those some link or TOC entry
+/// points to and, with `keep_all`, every other one but the vendor ones. Handlers
+/// that convert elements with an id themselves ask it for their anchor.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Anchors {
+    /// Ids in the chapter some link or TOC entry points to
+    pub(crate) referenced_ids: HashSet<String>,
+    /// Keep the anchors of ids nothing points to, except vendor ones
+    pub(crate) keep_all: bool,
+}
+
+impl Anchors {
+    /// Whether an element with this id gets an anchor
+    pub(crate) fn keeps(&self, id: &str) -> bool {
+        self.referenced_ids.contains(id) || (self.keep_all && !VENDOR_ID.is_match(id))
+    }
+
+    /// `<a id>` anchor for the id of an element, if it gets one
+    pub(crate) fn anchor_for(&self, node: &Node) -> Option<String> {
+        dom::anchor_id(node)
+            .filter(|id| self.keeps(id))
+            .map(|id| format!("<a id=\"{}\"></a>", escape_attr(&id)))
+    }
+}
+
+/// Puts an `<a id>` anchor in front of elements with an id the [`Anchors`] keep,
+/// so links to it keep working. Empty page breaks are dropped.
+pub(crate) struct AnchorHandler {
+    pub(crate) anchors: Arc<Anchors>,
+}
+
+impl AnchorHandler {
+    /// Every element an id can sit on, block, inline or list item. Table rows and
+    /// captions get their anchor from the table handler.
+    pub(crate) fn tags() -> Vec<&'static str> {
+        [BLOCK_TAGS, INLINE_TAGS, &["li"]].concat()
+    }
+}
+
+impl ElementHandler for AnchorHandler {
+    fn handle(&self, handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        if is_page_break(&element) {
+            return Some("".into());
+        }
+        let anchor = self.anchors.anchor_for(element.node);
+        let tag = element.tag.to_string();
+        let mut result = handlers.fallback(element)?;
+        let Some(anchor) = anchor else {
+            return Some(result);
+        };
+        if BLOCK_TAGS.contains(&tag.as_str()) || result.content.starts_with("\n\n") {
+            let content = result.content.trim_start_matches('\n');
+            result.content = format!("\n\n{anchor}\n\n{content}");
+        } else if tag == "li" {
+            // After the bullet, as a line of its own would end the list. Ordered
+            // list items get their number later, in front of the anchor.
+            let start = result.content.len() - result.content.trim_start_matches('\n').len();
+            let item = &result.content[start..];
+            let marker = item
+                .strip_prefix(['*', '-'])
+                .filter(|rest| rest.starts_with(' '))
+                .map_or(0, |rest| item.len() - rest.trim_start_matches(' ').len());
+            result.content.insert_str(start + marker, &anchor);
+        } else {
+            result.content.insert_str(0, &anchor);
+        }
+        Some(result)
+    }
+}
+
+fn is_page_break(element: &Element) -> bool {
+    let id = dom::attr(element.node, "id").unwrap_or_default();
+    let class = dom::attr(element.node, "class").unwrap_or_default();
+    let marked = element.tag == "mbp:pagebreak"
+        || PAGE_BREAK.is_match(&id)
+        || class
+            .split_whitespace()
+            .any(|class| PAGE_BREAK.is_match(class));
+    marked && dom::text_content(element.node).is_empty()
+}
@@ -0,0 +1,153 @@
+use super::anchor::Anchors;
+use crate::dom;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use htmd::{Element, Node};
+use markup5ever_rcdom::NodeData;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Classes marking callout numbers in code listings, e.g. O'Reilly's
+/// `<a class="co">` and Asciidoctor's `<i class="conum">`
+const CALLOUT_CLASSES: &[&str] = &["callout", "calloutnumber", "co", "conum"];
+
+/// Converts `<pre>` listings to fenced code blocks, keeping the whitespace of the
+/// listing exactly and tagging the block with the language the EPUB declares.
+/// Callout markers become comments of that language, and the anchors of ids inside
+/// the listing go in front of the block.
+pub(crate) struct CodeHandler {
+    pub(crate) anchors: Arc<Anchors>,
+}
+
+impl ElementHandler for CodeHandler {
+    fn handle(&self, _handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        let descendants = dom::descendants(element.node);
+        let language = code_language(element.node).or_else(|| {
+            descendants
+                .iter()
+                .filter(|node| dom::element_name(node) == Some("code"))
+                .find_map(|code| code_language(code))
+        });
+        let anchors = descendants
+            .iter()
+            .filter_map(|node| self.anchors.anchor_for(node))
+            .collect::<String>();
+        let mut code = String::new();
+        collect_code(element.node, comment_syntax(language.as_deref()), &mut code);
+        let code = code.trim_matches('\n');
+        let fence = crate::backtick_fence(code, 3);
+        let language = language.unwrap_or_default();
+        let block = format!("{fence}{language}\n{code}\n{fence}");
+        Some(if anchors.is_empty() {
+            format!("\n\n{block}\n\n").into()
+        } else {
+            format!("\n\n{anchors}\n\n{block}\n\n").into()
+        })
+    }
+}
+
+/// Language declared on an element through `data-code-language`, `data-lang`,
+/// a `language-*`/`lang-*` class or a SyntaxHighlighter `brush:` class
+fn code_language(node: &Node) -> Option<String> {
+    let language = dom::attr(node, "data-code-language")
+        .or_else(|| dom::attr(node, "data-lang"))
+        .or_else(|| {
+            let class = dom::attr(node, "class")?;
+            let mut classes = class.split_whitespace();
+            while let Some(class) = classes.next() {
+                if let Some(language) = class
+                    .strip_prefix("language-")
+                    .or_else(|| class.strip_prefix("lang-"))
+                {
+                    return Some(language.to_string());
+                }
+                if class == "brush:" {
+                    return classes.next().map(str::to_string);
+                }
+                if let Some(language) = class.strip_prefix("brush:") {
+                    return Some(language.to_string());
+                }
+            }
+            None
+        })?;
+    let language = language.trim().trim_end_matches(';').to_lowercase();
+    let language = match language.as_str() {
+        "" => return None,
+        "c++" => "cpp".to_string(),
+        "c#" => "csharp".to_string(),
+        "f#" => "fsharp".to_string(),
+        _ => language,
+    };
+    Some(language)
+}
+
+/// Start and end of a line comment in `language`
+fn comment_syntax(language: Option<&str>) -> (&'static str, &'static str) {
+    match language {
+        Some(
+            "bash" | "console" | "coffeescript" | "dockerfile" | "elixir" | "julia" | "makefile"
+            | "nginx" | "perl" | "powershell" | "python" | "r" | "ruby" | "sh" | "shell" | "toml"
+            | "yaml" | "yml" | "zsh",
+        ) => ("# ", ""),
+        Some("ada" | "elm" | "haskell" | "lua" | "sql") => ("-- ", ""),
+        Some("clojure" | "elisp" | "lisp" | "scheme") => ("; ", ""),
+        Some("erlang" | "latex" | "tex") => ("% ", ""),
+        Some("html" | "markdown" | "svg" | "xhtml" | "xml") => ("<!-- ", " -->"),
+        Some("css") => ("/* ", " */"),
+        _ => ("// ", ""),
+    }
+}
+
+fn collect_code(node: &Node, comment: (&str, &str), code: &mut String) {
+    let mut skip_callout_text = None;
+    for child in node.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { contents } => code.push_str(&contents.borrow()),
+            NodeData::Element { name, .. } if &name.local == "br" => code.push('\n'),
+            NodeData::Element { .. } => {
+                // Asciidoctor repeats the callout number in a `<b>` after the marker.
+                if skip_callout_text
+                    .take()
+                    .is_some_and(|marker| dom::text_content(child) == marker)
+                {
+                    continue;
+                }
+                if let Some(number) = callout_number(child) {
+                    let marker = format!("({number})");
+                    if !code.is_empty() && !code.ends_with(char::is_whitespace) {
+                        code.push(' ');
+                    }
+                    code.push_str(&format!("{}{}{}", comment.0, marker, comment.1));
+                    skip_callout_text = Some(marker);
+                } else {
+                    collect_code(child, comment, code);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Number of a callout marker element, `None` if `node` is no callout
+fn callout_number(node: &Rc<Node>) -> Option<String> {
+    let is_callout = dom::attr(node, "data-type").as_deref() == Some("callout")
+        || dom::attr(node, "class").is_some_and(|class| {
+            class
+                .split_whitespace()
+                .any(|class| CALLOUT_CLASSES.contains(&class))
+        });
+    if !is_callout {
+        return None;
+    }
+    let number = dom::attr(node, "data-value")
+        .or_else(|| Some(dom::text_content(node)).filter(|text| !text.is_empty()))
+        .or_else(|| {
+            dom::find_first(node, &|node| dom::attr(node, "alt").is_some())
+                .and_then(|node| dom::attr(&node, "alt"))
+        })
+        .unwrap_or_default();
+    Some(
+        number
+            .trim_matches(|c: char| c.is_whitespace() || c == '(' || c == ')')
+            .to_string(),
+    )
+}
@@ -0,0 +1,80 @@
+use super::anchor::Anchors;
+use super::table::is_kept_attr;
+use crate::dom;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use htmd::{Element, Node};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Converts `<dl>` to a Markdown definition list, which mdBook renders. Lists with
+/// several terms per definition or definitions of more than one paragraph are kept
+/// as cleaned up raw HTML instead.
+pub(crate) struct DefinitionListHandler {
+    pub(crate) anchors: Arc<Anchors>,
+}
+
+impl ElementHandler for DefinitionListHandler {
+    fn handle(&self, handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        let list = self
+            .definition_list(handlers, element.node)
+            .unwrap_or_else(|| {
+                dom::to_html(element.node, &|name, value| {
+                    is_kept_attr(name, value, &self.anchors)
+                })
+                .replace("<dt", "\n<dt")
+                .replace("<dd", "\n<dd")
+                .replace("</dl>", "\n</dl>")
+            });
+        Some(format!("\n\n{list}\n\n").into())
+    }
+}
+
+impl DefinitionListHandler {
+    fn definition_list(&self, handlers: &dyn Handlers, dl: &Node) -> Option<String> {
+        let mut items = Vec::new();
+        let mut last_was_term = false;
+        for entry in entries(dl) {
+            let content = self.entry_content(handlers, &entry)?;
+            if dom::element_name(&entry) == Some("dt") {
+                // A definition list item has a single term line
+                if last_was_term || content.contains('\n') {
+                    return None;
+                }
+                items.push(content);
+                last_was_term = true;
+            } else {
+                let item = items.last_mut()?;
+                item.push_str(&format!("\n: {}", content.replace('\n', "\n  ")));
+                last_was_term = false;
+            }
+        }
+        if items.is_empty() || last_was_term {
+            return None;
+        }
+        Some(items.join("\n\n"))
+    }
+
+    /// Markdown of a term or definition, `None` when it doesn't fit on a single paragraph
+    fn entry_content(&self, handlers: &dyn Handlers, entry: &Rc<Node>) -> Option<String> {
+        let content = handlers.walk_children(entry).content;
+        let content = content.trim();
+        if content.is_empty() || content.contains("\n\n") {
+            return None;
+        }
+        let anchor = self.anchors.anchor_for(entry).unwrap_or_default();
+        Some(format!("{anchor}{content}"))
+    }
+}
+
+/// `<dt>` and `<dd>` children of a list, including those grouped in a `<div>`
+fn entries(dl: &Node) -> Vec<Rc<Node>> {
+    let mut entries = Vec::new();
+    for child in dom::child_elements(dl, &["dt", "dd", "div"]) {
+        if dom::element_name(&child) == Some("div") {
+            entries.extend(dom::child_elements(&child, &["dt", "dd"]));
+        } else {
+            entries.push(child);
+        }
+    }
+    entries
+}
@@ -0,0 +1,54 @@
+use super::anchor::Anchors;
+use crate::{dom, escape_link_text};
+use htmd::Element;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use std::sync::Arc;
+
+/// Converts `<figure>` to its content followed right away by the caption, as a
+/// paragraph or a `<figcaption>` in a raw `<figure>` block. Images without alt
+/// text get the caption as alt text.
+pub(crate) struct FigureHandler {
+    pub(crate) caption_html: bool,
+    pub(crate) anchors: Arc<Anchors>,
+}
+
+impl ElementHandler for FigureHandler {
+    fn handle(&self, handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        let mut blocks = Vec::new();
+        let mut caption = None;
+        for child in element.node.children.borrow().iter() {
+            if dom::element_name(child) == Some("figcaption") {
+                let content = handlers.walk_children(child).content;
+                let anchor = self.anchors.anchor_for(child).unwrap_or_default();
+                caption = Some((
+                    format!("{anchor}{}", content.trim()),
+                    dom::text_content(child),
+                ));
+                continue;
+            }
+            let content = handlers
+                .handle(child)
+                .map(|result| result.content)
+                .unwrap_or_default();
+            let content = content.trim();
+            if !content.is_empty() {
+                blocks.push(content.to_string());
+            }
+        }
+        let mut content = blocks.join("\n\n");
+        if let Some((_, text)) = caption.as_ref().filter(|(_, text)| !text.is_empty()) {
+            content = content.replacen("![](", &format!("![{}](", escape_link_text(text)), 1);
+        }
+        let caption = caption
+            .map(|(caption, _)| caption)
+            .filter(|caption| !caption.is_empty());
+        let figure = match caption {
+            Some(caption) if self.caption_html => format!(
+                "<figure>\n\n{content}\n\n<figcaption>\n\n{caption}\n\n</figcaption>\n\n</figure>"
+            ),
+            Some(caption) => format!("{content}\n\n{caption}"),
+            None => content,
+        };
+        Some(format!("\n\n{}\n\n", figure.trim_matches('\n')).into())
+    }
+}
@@ -0,0 +1,81 @@
+use crate::dom;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use htmd::{Element, Node};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Shifts the headings of a chapter so its top heading lands on a given level
+pub(crate) struct HeadingLevels {
+    offset: isize,
+    top_level: usize,
+    demote_extra_top: bool,
+    seen_top: AtomicBool,
+    /// Set from the second top heading on, whose section moves down with it
+    past_first_section: AtomicBool,
+}
+
+impl HeadingLevels {
+    /// * `tree` - The chapter, used to find its top heading level
+    /// * `top_level` - The level the top heading is moved to
+    /// * `demote_extra_top` - Demote every top heading after the first one, and the
+    ///   headings under it, by one level
+    pub(crate) fn new(tree: &Rc<Node>, top_level: usize, demote_extra_top: bool) -> Self {
+        let min_level = dom::descendants(tree)
+            .iter()
+            .filter_map(|node| dom::element_name(node).and_then(heading_level))
+            .min()
+            .unwrap_or(top_level);
+        Self {
+            offset: top_level as isize - min_level as isize,
+            top_level,
+            demote_extra_top,
+            seen_top: AtomicBool::new(false),
+            past_first_section: AtomicBool::new(false),
+        }
+    }
+}
+
+impl ElementHandler for HeadingLevels {
+    fn handle(&self, handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        let level = heading_level(element.tag)?;
+        let mut new_level = (level as isize + self.offset).clamp(1, 6) as usize;
+        if new_level == self.top_level && self.seen_top.swap(true, Ordering::Relaxed) {
+            self.past_first_section.store(true, Ordering::Relaxed);
+        }
+        if self.demote_extra_top && self.past_first_section.load(Ordering::Relaxed) {
+            new_level = (new_level + 1).min(6);
+        }
+        let mut result = handlers.fallback(element)?;
+        result.content = set_heading_level(&result.content, new_level);
+        Some(result)
+    }
+}
+
+fn heading_level(tag: &str) -> Option<usize> {
+    match tag {
+        "h1" => Some(1),
+        "h2" => Some(2),
+        "h3" => Some(3),
+        "h4" => Some(4),
+        "h5" => Some(5),
+        "h6" => Some(6),
+        _ => None,
+    }
+}
+
+/// Replace the `#`s of the first ATX heading in `markdown`
+fn set_heading_level(markdown: &str, level: usize) -> String {
+    let mut rewritten = false;
+    let lines = markdown
+        .split('\n')
+        .map(|line| {
+            if rewritten || !crate::is_atx_heading(line) {
+                return line.to_string();
+            }
+            rewritten = true;
+            let text = line.trim_start().trim_start_matches('#');
+            format!("{}{}", "#".repeat(level), text)
+        })
+        .collect::<Vec<_>>();
+    lines.join("\n")
+}
@@ -0,0 +1,66 @@
+use crate::{dom, escape_attr};
+use htmd::Element;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+
+/// Small-caps classes recognized without any configuration
+const DEFAULT_SMALL_CAPS_CLASSES: &[&str] = &[
+    "sc",
+    "small-caps",
+    "smallcaps",
+    "smallcap",
+    "smcap",
+    "smcaps",
+];
+
+/// Keeps `<sub>`, `<sup>`, `<kbd>`, `<mark>` and `<abbr>` as inline HTML around
+/// their converted content, which htmd would flatten to plain text
+pub(crate) fn inline_html_handler(
+    handlers: &dyn Handlers,
+    element: Element,
+) -> Option<HandlerResult> {
+    let content = handlers.walk_children(element.node).content;
+    if content.trim().is_empty() {
+        return Some(content.into());
+    }
+    let title = (element.tag == "abbr")
+        .then(|| dom::attr(element.node, "title"))
+        .flatten()
+        .filter(|title| !title.trim().is_empty())
+        .map(|title| format!(" title=\"{}\"", escape_attr(title.trim())))
+        .unwrap_or_default();
+    let tag = element.tag;
+    Some(format!("<{tag}{title}>{content}</{tag}>").into())
+}
+
+/// Turns spans with a small-caps class into `<span style="font-variant: small-caps">`
+pub(crate) struct SmallCapsHandler {
+    classes: Vec<String>,
+}
+
+impl SmallCapsHandler {
+    pub(crate) fn new(extra_classes: &[String]) -> Self {
+        let classes = DEFAULT_SMALL_CAPS_CLASSES
+            .iter()
+            .map(|class| class.to_string())
+            .chain(extra_classes.iter().map(|class| class.to_lowercase()))
+            .collect();
+        Self { classes }
+    }
+}
+
+impl ElementHandler for SmallCapsHandler {
+    fn handle(&self, handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        let class = dom::attr(element.node, "class").unwrap_or_default();
+        let is_small_caps = class
+            .split_whitespace()
+            .any(|class| self.classes.contains(&class.to_lowercase()));
+        if !is_small_caps {
+            return handlers.fallback(element);
+        }
+        let content = handlers.walk_children(element.node).content;
+        if content.trim().is_empty() {
+            return Some(content.into());
+        }
+        Some(format!("<span style=\"font-variant: small-caps\">{content}</span>").into())
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{dom, escape_attr};
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use htmd::{Element, Node};
+
+/// Elements that get a `<span>` rather than a `<div>` wrapper
+const INLINE_TAGS: &[&str] = &[
+    "abbr", "b", "bdi", "bdo", "cite", "em", "i", "q", "span", "strong",
+];
+
+/// Keeps the `lang` and `dir` of passages whose language or direction differs from
+/// the surrounding text, by wrapping their Markdown in raw `<div lang="..">` or
+/// `<span lang="..">` HTML
+pub(crate) struct LangHandler {
+    /// Language of the book, which passages without a `lang` inherit
+    pub(crate) book_language: Option<String>,
+    /// `ltr` or `rtl`, as set in book.toml
+    pub(crate) book_direction: &'static str,
+}
+
+impl ElementHandler for LangHandler {
+    fn handle(&self, handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        // `<html>` doesn't go through the handlers, so `<body>` carries its `lang`
+        let (lang, inherited) = if element.tag == "body" {
+            (inherited_language(element.node), self.book_language.clone())
+        } else {
+            let inherited =
+                dom::parent(element.node).and_then(|parent| inherited_language(&parent));
+            (
+                own_language(element.node),
+                inherited.or_else(|| self.book_language.clone()),
+            )
+        };
+        let lang =
+            lang.filter(|lang| !inherited.is_some_and(|inherited| same_language(lang, &inherited)));
+        let dir = dom::attr(element.node, "dir")
+            .map(|dir| dir.trim().to_ascii_lowercase())
+            .filter(|dir| matches!(dir.as_str(), "ltr" | "rtl" | "auto"))
+            .filter(|dir| dir != self.book_direction);
+        let inline = INLINE_TAGS.contains(&element.tag);
+        let body = element.tag == "body";
+        let mut result = handlers.fallback(element)?;
+        let mut attrs = String::new();
+        if let Some(lang) = lang {
+            attrs.push_str(&format!(" lang=\"{}\"", escape_attr(&lang)));
+        }
+        if let Some(dir) = dir {
+            attrs.push_str(&format!(" dir=\"{dir}\""));
+        }
+        let content = result.content.trim_matches('\n');
+        if attrs.is_empty() || content.trim().is_empty() {
+            return Some(result);
+        }
+        if inline {
+            result.content = format!("<span{attrs}>{content}</span>");
+            return Some(result);
+        }
+        // The chapter title stays outside the wrapper, where part detection and
+        // `add_missing_chapter_title` look for it
+        let (heading, content) = if body {
+            split_leading_heading(content)
+        } else {
+            ("", content)
+        };
+        let (heading, content) = (heading.trim_matches('\n'), content.trim_matches('\n'));
+        if content.trim().is_empty() {
+            return Some(result);
+        }
+        // Blank lines around the content let Markdown inside the HTML block render
+        let div = format!("<div{attrs}>\n\n{content}\n\n</div>");
+        result.content = if heading.is_empty() {
+            format!("\n\n{div}\n\n")
+        } else {
+            format!("\n\n{heading}\n\n{div}\n\n")
+        };
+        Some(result)
+    }
+}
+
+/// Splits the anchors and heading a chapter starts with from the rest of it
+fn split_leading_heading(content: &str) -> (&str, &str) {
+    let mut end = 0;
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || crate::is_html_anchor(trimmed) {
+            end += line.len();
+        } else if crate::is_atx_heading(line.trim_end()) {
+            return content.split_at(end + line.len());
+        } else {
+            break;
+        }
+    }
+    ("", content)
+}
+
+/// Language of the nearest element with a `lang`, up from `node`
+fn inherited_language(node: &Node) -> Option<String> {
+    own_language(node).or_else(|| dom::parent(node).and_then(|parent| inherited_language(&parent)))
+}
+
+/// `lang`, or `xml:lang` in XHTML, of an element. An empty one means unknown.
+fn own_language(node: &Node) -> Option<String> {
+    dom::attr(node, "lang")
+        .or_else(|| dom::attr(node, "xml:lang"))
+        .map(|lang| lang.trim().to_string())
+        .filter(|lang| !lang.is_empty())
+}
+
+/// `en` and `en-US` count as the same language, `en` and `el` don't
+fn same_language(left: &str, right: &str) -> bool {
+    let left = left.to_ascii_lowercase();
+    let right = right.to_ascii_lowercase();
+    left == right
+        || left.starts_with(&format!("{right}-"))
+        || right.starts_with(&format!("{left}-"))
+}
@@ -0,0 +1,364 @@
+use crate::dom;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use htmd::{Element, Node};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Converts MathML to LaTeX for MathJax, as `\\( ... \\)` inline and `\\[ ... \\]`
+/// for `display="block"`, recording that the book uses math
+pub(crate) struct MathHandler {
+    pub(crate) found_math: Arc<AtomicBool>,
+}
+
+impl ElementHandler for MathHandler {
+    fn handle(&self, _handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        let tex = mathml_to_latex(element.node);
+        if tex.is_empty() {
+            return None;
+        }
+        self.found_math.store(true, Ordering::Relaxed);
+        let tex = escape_markdown(&tex);
+        let display = dom::attr(element.node, "display").as_deref() == Some("block")
+            || dom::attr(element.node, "mode").as_deref() == Some("display");
+        let content = if display {
+            format!("\n\n\\\\[ {tex} \\\\]\n\n")
+        } else {
+            format!("\\\\( {tex} \\\\)")
+        };
+        Some(content.into())
+    }
+}
+
+/// Markdown would eat backslashes in front of punctuation and turn `_`/`*` into
+/// emphasis, so escape them for MathJax to see the LaTeX as written
+fn escape_markdown(tex: &str) -> String {
+    let mut escaped = String::new();
+    for c in tex.chars() {
+        if matches!(c, '\\' | '_' | '*') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escape the characters LaTeX treats specially inside `\text{}`
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            '^' => escaped.push_str("\\^{}"),
+            '~' => escaped.push_str("\\~{}"),
+            '%' | '#' | '&' | '_' | '$' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn mathml_to_latex(node: &Rc<Node>) -> String {
+    // Books generated from LaTeX often carry the source along.
+    if let Some(annotation) = dom::find_first(node, &|node| {
+        dom::element_name(node) == Some("annotation")
+            && dom::attr(node, "encoding")
+                .is_some_and(|encoding| encoding.contains("tex") || encoding.contains("TeX"))
+    }) {
+        return dom::text_content(&annotation);
+    }
+    children_to_latex(node).trim().to_string()
+}
+
+fn children_to_latex(node: &Rc<Node>) -> String {
+    let mut tex = String::new();
+    for child in element_children(node) {
+        push_tex(&mut tex, &node_to_latex(&child));
+    }
+    tex
+}
+
+fn element_children(node: &Rc<Node>) -> Vec<Rc<Node>> {
+    node.children
+        .borrow()
+        .iter()
+        .filter(|child| dom::element_name(child).is_some())
+        .cloned()
+        .collect()
+}
+
+/// Append a piece of LaTeX, keeping a command like `\alpha` apart from a following letter
+fn push_tex(tex: &mut String, piece: &str) {
+    let ends_with_command = {
+        let letters = tex.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        letters.len() < tex.len() && letters.ends_with('\\')
+    };
+    if ends_with_command && piece.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        tex.push(' ');
+    }
+    tex.push_str(piece);
+}
+
+fn group(node: Option<&Rc<Node>>) -> String {
+    format!("{{{}}}", node.map(node_to_latex).unwrap_or_default())
+}
+
+fn node_to_latex(node: &Rc<Node>) -> String {
+    let children = element_children(node);
+    let arg = |i: usize| group(children.get(i));
+    match dom::element_name(node).unwrap_or_default() {
+        "mi" => identifier_to_latex(node),
+        "mn" => dom::text_content(node),
+        "mo" => operator_to_latex(&dom::text_content(node)),
+        "mtext" | "ms" => {
+            let text = dom::text_content(node);
+            if text.is_empty() {
+                String::new()
+            } else {
+                format!("\\text{{{}}}", escape_text(&text))
+            }
+        }
+        "mspace" => "\\ ".to_string(),
+        "msup" => format!("{}^{}", arg(0), arg(1)),
+        "msub" => format!("{}_{}", arg(0), arg(1)),
+        "msubsup" => format!("{}_{}^{}", arg(0), arg(1), arg(2)),
+        "mfrac" => format!("\\frac{}{}", arg(0), arg(1)),
+        "msqrt" => format!("\\sqrt{{{}}}", children_to_latex(node)),
+        "mroot" => format!(
+            "\\sqrt[{}]{}",
+            children.get(1).map(node_to_latex).unwrap_or_default(),
+            arg(0)
+        ),
+        "mover" => over_to_latex(&children),
+        "munder" => under_to_latex(&children),
+        "munderover" => format!("{}_{}^{}", arg(0), arg(1), arg(2)),
+        "mfenced" => fenced_to_latex(node, &children),
+        "menclose" => match dom::attr(node, "notation").as_deref() {
+            Some("box" | "roundedbox") => format!("\\boxed{{{}}}", children_to_latex(node)),
+            _ => children_to_latex(node),
+        },
+        "mtable" => {
+            let rows = children
+                .iter()
+                .map(|row| {
+                    element_children(row)
+                        .iter()
+                        .map(children_to_latex)
+                        .collect::<Vec<_>>()
+                        .join(" & ")
+                })
+                .collect::<Vec<_>>()
+                .join(" \\\\ ");
+            format!("\\begin{{matrix}} {rows} \\end{{matrix}}")
+        }
+        "annotation" | "annotation-xml" | "mphantom" => String::new(),
+        "semantics" => children.first().map(node_to_latex).unwrap_or_default(),
+        _ => children_to_latex(node),
+    }
+}
+
+fn identifier_to_latex(node: &Rc<Node>) -> String {
+    let text = dom::text_content(node);
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => {
+            let symbol = symbol_to_latex(c);
+            if dom::attr(node, "mathvariant").as_deref() == Some("normal") && c.is_alphabetic() {
+                format!("\\mathrm{{{symbol}}}")
+            } else {
+                symbol
+            }
+        }
+        _ if FUNCTIONS.contains(&text.as_str()) => format!("\\{text}"),
+        _ if text.is_empty() => String::new(),
+        _ => format!("\\mathrm{{{text}}}"),
+    }
+}
+
+/// Functions LaTeX has a command for
+const FUNCTIONS: &[&str] = &[
+    "arccos", "arcsin", "arctan", "cos", "cosh", "cot", "csc", "det", "dim", "exp", "gcd", "lim",
+    "ln", "log", "max", "min", "sec", "sin", "sinh", "sup", "tan", "tanh",
+];
+
+fn operator_to_latex(text: &str) -> String {
+    if FUNCTIONS.contains(&text) {
+        return format!("\\{text}");
+    }
+    let mut tex = String::new();
+    for c in text.chars() {
+        push_tex(&mut tex, &symbol_to_latex(c));
+    }
+    tex
+}
+
+fn over_to_latex(children: &[Rc<Node>]) -> String {
+    let base = group(children.first());
+    let over = children.get(1).map(node_to_latex).unwrap_or_default();
+    let accent = match over.as_str() {
+        "^" | "\\hat" | "ˆ" => Some("\\hat"),
+        "\\bar" | "-" | "¯" | "‾" | "_" => Some("\\overline"),
+        "\\to" | "\\rightarrow" => Some("\\vec"),
+        "~" | "\\sim" | "˜" => Some("\\tilde"),
+        "." | "˙" | "\\cdot" => Some("\\dot"),
+        _ => None,
+    };
+    match accent {
+        Some(accent) => format!("{accent}{base}"),
+        None if is_large_operator(children.first()) => format!("{base}^{{{over}}}"),
+        None => format!("\\overset{{{over}}}{base}"),
+    }
+}
+
+fn under_to_latex(children: &[Rc<Node>]) -> String {
+    let base = group(children.first());
+    let under = children.get(1).map(node_to_latex).unwrap_or_default();
+    if is_large_operator(children.first()) {
+        format!("{base}_{{{under}}}")
+    } else {
+        format!("\\underset{{{under}}}{base}")
+    }
+}
+
+fn is_large_operator(node: Option<&Rc<Node>>) -> bool {
+    node.map(node_to_latex).is_some_and(|tex| {
+        [
+            "\\sum", "\\prod", "\\int", "\\bigcup", "\\bigcap", "\\lim", "\\max", "\\min",
+        ]
+        .contains(&tex.as_str())
+    })
+}
+
+fn fenced_to_latex(node: &Rc<Node>, children: &[Rc<Node>]) -> String {
+    let open = dom::attr(node, "open").unwrap_or_else(|| "(".to_string());
+    let close = dom::attr(node, "close").unwrap_or_else(|| ")".to_string());
+    let separator = dom::attr(node, "separators")
+        .and_then(|separators| separators.trim().chars().next())
+        .map(|separator| separator.to_string())
+        .unwrap_or_else(|| ",".to_string());
+    let content = children
+        .iter()
+        .map(node_to_latex)
+        .collect::<Vec<_>>()
+        .join(&separator);
+    format!(
+        "\\left{} {} \\right{}",
+        fence_to_latex(&open),
+        content,
+        fence_to_latex(&close)
+    )
+}
+
+fn fence_to_latex(fence: &str) -> String {
+    match fence {
+        "" => ".".to_string(),
+        "{" => "\\{".to_string(),
+        "}" => "\\}".to_string(),
+        _ => fence.chars().map(symbol_to_latex).collect(),
+    }
+}
+
+fn symbol_to_latex(c: char) -> String {
+    let command = match c {
+        'α' => "\\alpha",
+        'β' => "\\beta",
+        'γ' => "\\gamma",
+        'δ' => "\\delta",
+        'ε' | 'ϵ' => "\\epsilon",
+        'ζ' => "\\zeta",
+        'η' => "\\eta",
+        'θ' => "\\theta",
+        'ι' => "\\iota",
+        'κ' => "\\kappa",
+        'λ' => "\\lambda",
+        'μ' => "\\mu",
+        'ν' => "\\nu",
+        'ξ' => "\\xi",
+        'π' => "\\pi",
+        'ρ' => "\\rho",
+        'σ' => "\\sigma",
+        'τ' => "\\tau",
+        'υ' => "\\upsilon",
+        'φ' | 'ϕ' => "\\phi",
+        'χ' => "\\chi",
+        'ψ' => "\\psi",
+        'ω' => "\\omega",
+        'Γ' => "\\Gamma",
+        'Δ' => "\\Delta",
+        'Θ' => "\\Theta",
+        'Λ' => "\\Lambda",
+        'Ξ' => "\\Xi",
+        'Π' => "\\Pi",
+        'Σ' => "\\Sigma",
+        'Φ' => "\\Phi",
+        'Ψ' => "\\Psi",
+        'Ω' => "\\Omega",
+        '−' => "-",
+        '×' => "\\times",
+        '·' | '⋅' => "\\cdot",
+        '÷' => "\\div",
+        '±' => "\\pm",
+        '∓' => "\\mp",
+        '≤' => "\\le",
+        '≥' => "\\ge",
+        '≠' => "\\ne",
+        '≈' => "\\approx",
+        '≡' => "\\equiv",
+        '∼' => "\\sim",
+        '∝' => "\\propto",
+        '<' => "\\lt",
+        '>' => "\\gt",
+        '∞' => "\\infty",
+        '→' => "\\to",
+        '←' => "\\leftarrow",
+        '↔' => "\\leftrightarrow",
+        '⇒' => "\\Rightarrow",
+        '⇔' => "\\Leftrightarrow",
+        '∑' => "\\sum",
+        '∏' => "\\prod",
+        '∫' => "\\int",
+        '∮' => "\\oint",
+        '∂' => "\\partial",
+        '∇' => "\\nabla",
+        '√' => "\\surd",
+        '∈' => "\\in",
+        '∉' => "\\notin",
+        '∀' => "\\forall",
+        '∃' => "\\exists",
+        '∅' => "\\emptyset",
+        '∪' => "\\cup",
+        '∩' => "\\cap",
+        '⋃' => "\\bigcup",
+        '⋂' => "\\bigcap",
+        '⊂' => "\\subset",
+        '⊆' => "\\subseteq",
+        '⊃' => "\\supset",
+        '⊇' => "\\supseteq",
+        '∧' => "\\wedge",
+        '∨' => "\\vee",
+        '¬' => "\\neg",
+        '…' => "\\ldots",
+        '⋯' => "\\cdots",
+        '′' => "'",
+        '″' => "''",
+        '°' => "^\\circ",
+        'ℝ' => "\\mathbb{R}",
+        'ℕ' => "\\mathbb{N}",
+        'ℤ' => "\\mathbb{Z}",
+        'ℚ' => "\\mathbb{Q}",
+        'ℂ' => "\\mathbb{C}",
+        '{' => "\\{",
+        '}' => "\\}",
+        '%' => "\\%",
+        '#' => "\\#",
+        '&' => "\\&",
+        '$' => "\\$",
+        // Invisible times, function application and separator
+        '\u{2061}' | '\u{2062}' | '\u{2063}' => "",
+        _ => return c.to_string(),
+    };
+    command.to_string()
+}
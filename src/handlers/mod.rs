@@ -0,0 +1,14 @@
+pub(crate) mod admonition;
+pub(crate) mod anchor;
+pub(crate) mod code;
+pub(crate) mod definitions;
+pub(crate) mod figure;
+pub(crate) mod headings;
+pub(crate) mod inline;
+pub(crate) mod lang;
+pub(crate) mod math;
+pub(crate) mod ruby;
+pub(crate) mod style;
+pub(crate) mod svg;
+pub(crate) mod table;
+pub(crate) mod verse;
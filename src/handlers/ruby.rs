@@ -0,0 +1,41 @@
+use crate::dom;
+use htmd::Element;
+use htmd::element_handler::{HandlerResult, Handlers};
+use markup5ever_rcdom::NodeData;
+
+/// Keeps `<ruby>` as raw inline HTML, which mdBook renders as is
+pub(crate) fn ruby_html_handler(
+    _handlers: &dyn Handlers,
+    element: Element,
+) -> Option<HandlerResult> {
+    Some(dom::to_html(element.node, &|name, _| name == "lang").into())
+}
+
+/// Renders `<ruby>` as `base(reading)` text, e.g. `漢字(かんじ)`
+pub(crate) fn ruby_text_handler(
+    _handlers: &dyn Handlers,
+    element: Element,
+) -> Option<HandlerResult> {
+    let mut text = String::new();
+    let mut base = String::new();
+    for child in element.node.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { contents } => base.push_str(contents.borrow().trim()),
+            NodeData::Element { name, .. } => match &*name.local {
+                "rp" => {}
+                "rt" | "rtc" => {
+                    let reading = dom::text_content(child);
+                    text.push_str(&base);
+                    if !reading.is_empty() {
+                        text.push_str(&format!("({reading})"));
+                    }
+                    base.clear();
+                }
+                _ => base.push_str(&dom::text_content(child)),
+            },
+            _ => {}
+        }
+    }
+    text.push_str(&base);
+    Some(text.into())
+}
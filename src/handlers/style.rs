@@ -0,0 +1,119 @@
+use crate::css::{Stylesheet, TextStyle};
+use crate::dom;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use htmd::{Element, Node};
+use std::sync::Arc;
+
+/// Elements that are already italic, bold or monospace, so styled descendants
+/// don't repeat the formatting
+const ITALIC_TAGS: &[&str] = &["cite", "dfn", "em", "i", "var"];
+const BOLD_TAGS: &[&str] = &["b", "h1", "h2", "h3", "h4", "h5", "h6", "strong", "th"];
+const STRIKETHROUGH_TAGS: &[&str] = &["del", "s", "strike"];
+const MONOSPACE_TAGS: &[&str] = &["code", "kbd", "pre", "samp", "tt"];
+
+/// Infers emphasis, strong, strikethrough and code spans from the stylesheet
+/// rules and `style` of spans and paragraphs, as Calibre-converted books rarely
+/// use `<em>` and friends. Centered paragraphs and divs get a centered raw `<div>`.
+pub(crate) struct StyleHandler {
+    pub(crate) stylesheet: Arc<Stylesheet>,
+}
+
+impl ElementHandler for StyleHandler {
+    fn handle(&self, handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        let style = self.stylesheet.style(element.node);
+        if style == TextStyle::default() {
+            return handlers.fallback(element);
+        }
+        let inherited = dom::parent(element.node)
+            .map(|parent| self.inherited_style(&parent))
+            .unwrap_or_default();
+        let tag = element.tag.to_string();
+        let node = element.node.clone();
+        // A code span would drop the links and markup of child elements
+        let plain_text = node
+            .children
+            .borrow()
+            .iter()
+            .all(|child| dom::element_name(child).is_none());
+        let code = (tag == "span" && style.monospace && !inherited.monospace && plain_text)
+            .then(|| dom::text_content(&node))
+            .filter(|text| !text.is_empty());
+        let mut result = handlers.fallback(element)?;
+        let content = match code {
+            Some(text) => code_span(&text),
+            None => result.content.trim_matches('\n').to_string(),
+        };
+        if content.trim().is_empty() {
+            return Some(result);
+        }
+        let mut content = if tag == "div" {
+            content
+        } else {
+            let mut delimiters = String::new();
+            if style.strikethrough && !inherited.strikethrough {
+                delimiters.push_str("~~");
+            }
+            if style.bold && !inherited.bold {
+                delimiters.push_str("**");
+            }
+            if style.italic && !inherited.italic {
+                delimiters.push('*');
+            }
+            emphasize(&content, &delimiters)
+        };
+        if style.centered && tag != "span" {
+            content = format!("<div style=\"text-align: center\">\n\n{content}\n\n</div>");
+        }
+        result.content = if tag == "span" {
+            content
+        } else {
+            format!("\n\n{content}\n\n")
+        };
+        Some(result)
+    }
+}
+
+impl StyleHandler {
+    /// Formatting an element gets from its ancestors
+    fn inherited_style(&self, node: &Node) -> TextStyle {
+        let mut inherited = dom::parent(node)
+            .map(|parent| self.inherited_style(&parent))
+            .unwrap_or_default();
+        let Some(tag) = dom::element_name(node) else {
+            return inherited;
+        };
+        let style = self.stylesheet.style(node);
+        inherited.italic |= style.italic || ITALIC_TAGS.contains(&tag);
+        inherited.bold |= style.bold || BOLD_TAGS.contains(&tag);
+        inherited.strikethrough |= style.strikethrough || STRIKETHROUGH_TAGS.contains(&tag);
+        inherited.monospace |= style.monospace || MONOSPACE_TAGS.contains(&tag);
+        inherited
+    }
+}
+
+/// Wrap content in emphasis delimiters, keeping surrounding whitespace outside as
+/// delimiters next to whitespace don't count. Multi-paragraph content is left alone.
+fn emphasize(content: &str, delimiters: &str) -> String {
+    let core = content.trim();
+    if delimiters.is_empty() || core.contains("\n\n") {
+        return content.to_string();
+    }
+    let start = content.len() - content.trim_start().len();
+    let end = start + core.len();
+    let closing = delimiters.chars().rev().collect::<String>();
+    format!(
+        "{}{delimiters}{core}{closing}{}",
+        &content[..start],
+        &content[end..]
+    )
+}
+
+/// Inline code with a backtick fence longer than any run of backticks in the text
+fn code_span(text: &str) -> String {
+    let fence = crate::backtick_fence(text, 1);
+    if text.starts_with('`') || text.ends_with('`') {
+        format!("{fence} {text} {fence}")
+    } else {
+        format!("{fence}{text}{fence}")
+    }
+}
@@ -0,0 +1,81 @@
+use super::anchor::Anchors;
+use crate::dom;
+use crate::options::SvgMode;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use htmd::{Element, Node};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+/// An inline SVG taken out of a chapter, to be written next to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ExtractedSvg {
+    pub(crate) file_name: String,
+    pub(crate) content: String,
+}
+
+/// Converts inline `<svg>` elements, which htmd would drop, according to [`SvgMode`].
+/// `xlink:href`s become plain `href`s, so they are rewritten like any other link.
+pub(crate) struct SvgHandler {
+    pub(crate) mode: SvgMode,
+    /// Prefix for the names of extracted files, unique per chapter
+    pub(crate) file_prefix: String,
+    pub(crate) extracted: Arc<Mutex<Vec<ExtractedSvg>>>,
+    pub(crate) anchors: Arc<Anchors>,
+}
+
+impl ElementHandler for SvgHandler {
+    fn handle(&self, _handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        let svg = element.node;
+        let image = match self.mode {
+            SvgMode::Inline => None,
+            SvgMode::Extract => Some(self.extract(svg)),
+            SvgMode::Unwrap => unwrap_raster_image(svg),
+        };
+        let Some(image) = image else {
+            return Some(dom::to_html(svg, &|_, _| true).into());
+        };
+        let image = format!(
+            "![{}]({})",
+            alt_text(svg),
+            crate::escape_link_destination(&image)
+        );
+        let anchor = self.anchors.anchor_for(svg).unwrap_or_default();
+        Some(format!("{anchor}{image}").into())
+    }
+}
+
+impl SvgHandler {
+    /// Store the SVG as a standalone file and return its name
+    fn extract(&self, svg: &Rc<Node>) -> String {
+        let mut extracted = self.extracted.lock().expect("unreachable");
+        let file_name = format!("{}-{}.svg", self.file_prefix, extracted.len() + 1);
+        let html = dom::to_html(svg, &|name, _| name != "id");
+        let content = match html.strip_prefix("<svg") {
+            Some(rest) => format!("<svg xmlns=\"http://www.w3.org/2000/svg\"{rest}\n"),
+            None => html,
+        };
+        extracted.push(ExtractedSvg {
+            file_name: file_name.clone(),
+            content,
+        });
+        file_name
+    }
+}
+
+/// The `href` of the raster image an SVG wraps, as covers commonly do
+fn unwrap_raster_image(svg: &Rc<Node>) -> Option<String> {
+    let image = dom::find_first(svg, &|node| dom::element_name(node) == Some("image"))?;
+    dom::attr(&image, "href").filter(|href| !href.trim().is_empty())
+}
+
+/// Alt text from the SVG's `aria-label` or `<title>`
+fn alt_text(svg: &Rc<Node>) -> String {
+    dom::attr(svg, "aria-label")
+        .or_else(|| {
+            dom::child_elements(svg, &["title"])
+                .first()
+                .map(|title| dom::text_content(title))
+        })
+        .unwrap_or_default()
+        .replace(['[', ']'], "")
+}
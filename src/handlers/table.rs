@@ -0,0 +1,189 @@
+use super::anchor::Anchors;
+use crate::dom;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use htmd::{Element, Node};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Elements a GitHub-flavored Markdown table cell can't hold
+const BLOCK_TAGS: &[&str] = &[
+    "blockquote",
+    "dl",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "ol",
+    "pre",
+    "table",
+    "ul",
+];
+
+/// Attributes kept when a table falls back to raw HTML, everything else is styling
+const KEPT_ATTRS: &[&str] = &[
+    "alt", "colspan", "dir", "headers", "href", "id", "lang", "rowspan", "scope", "src", "title",
+];
+
+/// Whether an attribute is kept when a table or definition list falls back to raw
+/// HTML. Ids are kept when they would get an anchor.
+pub(crate) fn is_kept_attr(name: &str, value: &str, anchors: &Anchors) -> bool {
+    KEPT_ATTRS.contains(&name) && (name != "id" || anchors.keeps(value.trim()))
+}
+
+/// Converts simple grids to GitHub-flavored Markdown tables and everything else
+/// (spanning cells, block content in cells) to cleaned up raw HTML. The caption
+/// becomes a paragraph in front of the table.
+pub(crate) struct TableHandler {
+    pub(crate) anchors: Arc<Anchors>,
+}
+
+impl ElementHandler for TableHandler {
+    fn handle(&self, handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        let rows = table_rows(element.node);
+        if rows.is_empty() {
+            let content = handlers.walk_children(element.node).content;
+            return Some(format!("\n\n{}\n\n", content.trim_matches('\n')).into());
+        }
+        let table = gfm_table(handlers, &rows, &self.anchors)
+            .unwrap_or_else(|| raw_html_table(element.node, &self.anchors));
+        let caption = dom::child_elements(element.node, &["caption"])
+            .first()
+            .map(|caption| {
+                let anchor = self.anchors.anchor_for(caption).unwrap_or_default();
+                let content = handlers.walk_children(caption).content;
+                format!("{anchor}{}", content.trim())
+            })
+            .filter(|caption| !caption.is_empty());
+        let content = match caption {
+            Some(caption) => format!("\n\n{caption}\n\n{table}\n\n"),
+            None => format!("\n\n{table}\n\n"),
+        };
+        Some(content.into())
+    }
+}
+
+/// Rows of a table, each with whether it sits in `<thead>`
+fn table_rows(table: &Node) -> Vec<(Rc<Node>, bool)> {
+    let mut rows = Vec::new();
+    for child in table.children.borrow().iter() {
+        match dom::element_name(child) {
+            Some("tr") => rows.push((child.clone(), false)),
+            Some(section @ ("thead" | "tbody" | "tfoot")) => rows.extend(
+                dom::child_elements(child, &["tr"])
+                    .into_iter()
+                    .map(|row| (row, section == "thead")),
+            ),
+            _ => {}
+        }
+    }
+    rows
+}
+
+fn gfm_table(
+    handlers: &dyn Handlers,
+    rows: &[(Rc<Node>, bool)],
+    anchors: &Anchors,
+) -> Option<String> {
+    let mut cells = Vec::new();
+    for (row, _) in rows {
+        let mut row_cells = Vec::new();
+        for cell in dom::child_elements(row, &["td", "th"]) {
+            if is_spanning(&cell) || has_block_content(&cell) {
+                return None;
+            }
+            row_cells.push(gfm_cell(handlers, &cell, anchors)?);
+        }
+        // A row has no place for an anchor of its own, its first cell does
+        if let Some(anchor) = anchors.anchor_for(row)
+            && let Some(first_cell) = row_cells.first_mut()
+        {
+            first_cell.insert_str(0, &anchor);
+        }
+        cells.push(row_cells);
+    }
+    let columns = cells.iter().map(Vec::len).max().unwrap_or_default();
+    if columns == 0 {
+        return None;
+    }
+
+    let (first_row, in_thead) = &rows[0];
+    let first_row_is_header = *in_thead
+        || dom::child_elements(first_row, &["td", "th"])
+            .iter()
+            .all(|cell| dom::element_name(cell) == Some("th"));
+    // GFM tables need a header row, leave it empty when the table has none.
+    let header = if first_row_is_header {
+        cells.remove(0)
+    } else {
+        Vec::new()
+    };
+
+    let mut lines = vec![
+        gfm_row(&header, columns),
+        gfm_row(&vec!["---".to_string(); columns], columns),
+    ];
+    lines.extend(cells.iter().map(|row| gfm_row(row, columns)));
+    Some(lines.join("\n"))
+}
+
+fn gfm_row(cells: &[String], columns: usize) -> String {
+    let mut row = String::from("|");
+    for i in 0..columns {
+        let cell = cells.get(i).map(String::as_str).unwrap_or_default();
+        row.push_str(&format!(" {cell} |"));
+    }
+    row
+}
+
+fn gfm_cell(handlers: &dyn Handlers, cell: &Rc<Node>, anchors: &Anchors) -> Option<String> {
+    let content = handlers.walk_children(cell).content;
+    let content = content.trim();
+    if content.contains("\n\n") {
+        return None;
+    }
+    let content = content
+        .lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join("<br>")
+        .replace('|', "\\|");
+    let anchor = anchors.anchor_for(cell).unwrap_or_default();
+    Some(format!("{anchor}{content}"))
+}
+
+fn is_spanning(cell: &Node) -> bool {
+    ["colspan", "rowspan"].iter().any(|name| {
+        dom::attr(cell, name)
+            .and_then(|span| span.trim().parse::<usize>().ok())
+            .is_some_and(|span| span > 1)
+    })
+}
+
+fn has_block_content(cell: &Rc<Node>) -> bool {
+    dom::descendants(cell)
+        .iter()
+        .any(|node| dom::element_name(node).is_some_and(|name| BLOCK_TAGS.contains(&name)))
+}
+
+/// The table as raw HTML without styling attributes or caption. The `<table>`'s own
+/// id is left to the anchor in front of it.
+fn raw_html_table(table: &Node, anchors: &Anchors) -> String {
+    let mut html = String::from("<table>");
+    for child in table.children.borrow().iter() {
+        if dom::element_name(child) != Some("caption") {
+            html.push_str(&dom::to_html(child, &|name, value| {
+                is_kept_attr(name, value, anchors)
+            }));
+        }
+    }
+    html.push_str("</table>");
+    html.replace("<tr", "\n<tr")
+        .replace("</table>", "\n</table>")
+        .lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
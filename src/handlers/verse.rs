@@ -0,0 +1,175 @@
+use super::anchor::Anchors;
+use crate::dom;
+use htmd::element_handler::{ElementHandler, HandlerResult, Handlers};
+use htmd::{Element, Node};
+use regex::Regex;
+use std::rc::Rc;
+use std::sync::{Arc, LazyLock};
+
+/// Verse classes recognized without any configuration
+const DEFAULT_CLASSES: &[&str] = &["lg", "poem", "poetry", "stanza", "verse"];
+
+/// Elements holding verse lines or stanzas. Spans count too when they have a
+/// `line` or indentation class.
+const LINE_TAGS: &[&str] = &["blockquote", "div", "p", "section"];
+
+/// Elements in a poem that are converted as blocks of their own, like a title
+const BLOCK_TAGS: &[&str] = &[
+    "dl", "figure", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "ol", "pre", "table", "ul",
+];
+
+/// Capture the level of an indentation class like `indent2` or `i2`
+static INDENT_CLASS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:indent-?|i)(\d*)$").expect("unreachable"));
+
+/// Converts poems to lines ending in hard line breaks, with stanzas as paragraphs
+/// and indentation classes as leading non-breaking spaces
+pub(crate) struct VerseHandler {
+    classes: Vec<String>,
+    anchors: Arc<Anchors>,
+}
+
+impl VerseHandler {
+    pub(crate) fn new(extra_classes: &[String], anchors: Arc<Anchors>) -> Self {
+        let classes = DEFAULT_CLASSES
+            .iter()
+            .map(|class| class.to_string())
+            .chain(extra_classes.iter().map(|class| class.to_lowercase()))
+            .collect();
+        Self { classes, anchors }
+    }
+
+    fn is_verse(&self, node: &Node) -> bool {
+        classes(node).any(|class| self.classes.contains(&class))
+    }
+}
+
+impl ElementHandler for VerseHandler {
+    fn handle(&self, handlers: &dyn Handlers, element: Element) -> Option<HandlerResult> {
+        if !self.is_verse(element.node) {
+            return handlers.fallback(element);
+        }
+        let blocks = verse_blocks(handlers, element.node, &self.anchors)
+            .into_iter()
+            .filter(|block| !block.trim().is_empty())
+            .collect::<Vec<_>>();
+        Some(format!("\n\n{}\n\n", blocks.join("\n\n")).into())
+    }
+}
+
+/// Stanzas of a poem with their lines joined by hard breaks, and any other content
+fn verse_blocks(handlers: &dyn Handlers, node: &Rc<Node>, anchors: &Anchors) -> Vec<String> {
+    if !has_line_children(node) {
+        return stanzas(&handlers.walk_children(node).content, indent_level(node))
+            .into_iter()
+            .map(|lines| lines.join("  \n"))
+            .collect();
+    }
+    let mut blocks = Vec::new();
+    let mut stanza = Vec::new();
+    for child in node.children.borrow().iter() {
+        if !is_line_element(child) {
+            let content = handlers
+                .handle(child)
+                .map(|result| result.content)
+                .unwrap_or_default();
+            let content = content.trim();
+            if content.is_empty() {
+                continue;
+            }
+            if dom::element_name(child).is_some_and(|name| BLOCK_TAGS.contains(&name)) {
+                flush_stanza(&mut stanza, &mut blocks);
+                blocks.push(content.to_string());
+            } else {
+                stanza.push(content.to_string());
+            }
+            continue;
+        }
+        if has_line_children(child) {
+            flush_stanza(&mut stanza, &mut blocks);
+            blocks.extend(verse_blocks(handlers, child, anchors));
+            continue;
+        }
+        let mut child_stanzas =
+            stanzas(&handlers.walk_children(child).content, indent_level(child));
+        if let Some(anchor) = anchors.anchor_for(child)
+            && let Some(line) = child_stanzas
+                .first_mut()
+                .and_then(|lines| lines.first_mut())
+        {
+            line.insert_str(0, &anchor);
+        }
+        // A single line continues the stanza, a line group is a stanza of its own
+        if let [lines] = child_stanzas.as_slice()
+            && lines.len() == 1
+        {
+            stanza.push(lines[0].clone());
+        } else {
+            flush_stanza(&mut stanza, &mut blocks);
+            blocks.extend(child_stanzas.into_iter().map(|lines| lines.join("  \n")));
+        }
+    }
+    flush_stanza(&mut stanza, &mut blocks);
+    blocks
+}
+
+fn flush_stanza(stanza: &mut Vec<String>, blocks: &mut Vec<String>) {
+    if !stanza.is_empty() {
+        blocks.push(stanza.join("  \n"));
+        stanza.clear();
+    }
+}
+
+fn has_line_children(node: &Node) -> bool {
+    node.children
+        .borrow()
+        .iter()
+        .any(|child| is_line_element(child))
+}
+
+fn is_line_element(node: &Node) -> bool {
+    match dom::element_name(node) {
+        Some("span") => classes(node).any(|class| class == "line" || INDENT_CLASS.is_match(&class)),
+        Some(name) => LINE_TAGS.contains(&name),
+        None => false,
+    }
+}
+
+/// Split converted Markdown into stanzas of lines at its line breaks, with an
+/// empty line (a double `<br>`) separating stanzas
+fn stanzas(markdown: &str, indent_level: usize) -> Vec<Vec<String>> {
+    let indent = "&nbsp;&nbsp;".repeat(indent_level);
+    let mut stanzas = vec![Vec::new()];
+    for line in markdown.lines().map(str::trim) {
+        if line.is_empty() {
+            stanzas.push(Vec::new());
+        } else {
+            stanzas
+                .last_mut()
+                .expect("unreachable")
+                .push(format!("{indent}{line}"));
+        }
+    }
+    stanzas.retain(|lines| !lines.is_empty());
+    stanzas
+}
+
+/// Indentation level from a class like `indent`, `indent2` or `i2`
+fn indent_level(node: &Node) -> usize {
+    classes(node)
+        .find_map(|class| {
+            INDENT_CLASS
+                .captures(&class)
+                .map(|caps| caps[1].parse().unwrap_or(1))
+        })
+        .unwrap_or_default()
+}
+
+fn classes(node: &Node) -> impl Iterator<Item = String> {
+    dom::attr(node, "class")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .into_iter()
+}
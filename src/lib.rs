@@ -1,15 +1,38 @@
+mod css;
+mod dom;
 pub mod error;
+mod handlers;
+pub mod options;
+pub mod report;
 
+use css::Stylesheet;
 use epub::doc::{EpubDoc, NavPoint};
 use error::Error;
-use htmd::element_handler::{HandlerResult, Handlers};
-use mdbook_core::config::BookConfig;
-use regex::{Captures, Regex};
-use std::collections::HashMap;
+use handlers::admonition::AdmonitionHandler;
+use handlers::anchor::{AnchorHandler, Anchors};
+use handlers::code::CodeHandler;
+use handlers::definitions::DefinitionListHandler;
+use handlers::figure::FigureHandler;
+use handlers::headings::HeadingLevels;
+use handlers::inline::{SmallCapsHandler, inline_html_handler};
+use handlers::lang::LangHandler;
+use handlers::math::MathHandler;
+use handlers::ruby::{ruby_html_handler, ruby_text_handler};
+use handlers::style::StyleHandler;
+use handlers::svg::{ExtractedSvg, SvgHandler};
+use handlers::table::TableHandler;
+use handlers::verse::VerseHandler;
+use mdbook_core::config::{BookConfig, TextDirection};
+use options::{AdmonitionStyle, BrokenLinkMode, ConvertOptions, RubyMode, SvgMode};
+use regex::Regex;
+use report::{Report, Warning};
+use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
 use std::io::{Read, Seek};
 use std::path::{Component, Path, PathBuf};
-use std::sync::LazyLock;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
 use std::{fs, io};
 
 /// Convert an EPUB file to MDBook format
@@ -25,6 +48,25 @@ pub fn convert_epub_to_mdbook(
     output_dir: impl AsRef<Path>,
     create_subdir: bool,
 ) -> Result<(), Error> {
+    convert_epub_to_mdbook_with_options(
+        epub_path,
+        output_dir,
+        create_subdir,
+        &ConvertOptions::default(),
+    )
+    .map(|_| ())
+}
+
+/// Convert an EPUB file to MDBook format with custom [`ConvertOptions`]
+///
+/// See [`convert_epub_to_mdbook`] for the meaning of the other arguments. Problems
+/// that don't stop the conversion are collected in the returned [`Report`].
+pub fn convert_epub_to_mdbook_with_options(
+    epub_path: impl AsRef<Path>,
+    output_dir: impl AsRef<Path>,
+    create_subdir: bool,
+    options: &ConvertOptions,
+) -> Result<Report, Error> {
     let epub_path = epub_path.as_ref();
     if !epub_path.is_file() {
         return Err(Error::NotAFile(epub_path.display().to_string()));
@@ -41,34 +83,70 @@ pub fn convert_epub_to_mdbook(
     }
     fs::create_dir_all(output_dir.join("src"))?;
 
+    let mut report = Report::default();
     let mut epub_doc = EpubDoc::new(epub_path)?;
-    let (summary_md, html_to_md) = generate_summary_md(&epub_doc);
-    let html_to_title = collect_chapter_titles(&epub_doc, &html_to_md);
-    extract_chapters_and_resources(&mut epub_doc, &output_dir, &html_to_md, &html_to_title)?;
+    let BookSummary {
+        summary_md,
+        html_to_md,
+        html_to_title,
+    } = summarize_book(&mut epub_doc, options, &mut report.warnings)?;
+    let locale = BookLocale::detect(&mut epub_doc);
+    let features = BookFeatures::default();
+    let (chapters, broken_links) = extract_chapters_and_resources(
+        &mut epub_doc,
+        &output_dir,
+        &html_to_md,
+        &html_to_title,
+        options,
+        &locale,
+        &features,
+    )?;
+    report.warnings.extend(broken_links);
+    report.warnings.extend(check_fragment_links(&chapters));
     fs::write(output_dir.join("src/SUMMARY.md"), summary_md)?;
-    write_book_toml(&epub_doc, &output_dir)?;
-    Ok(())
+    write_book_toml(&epub_doc, &output_dir, options, &locale, &features)?;
+    Ok(report)
 }
 
 fn epub_nav_to_md(
     nav: &NavPoint,
     indent: usize,
     html_to_md: &HashMap<PathBuf, PathBuf>,
+    warnings: &mut Vec<Warning>,
 ) -> Option<String> {
     let (content_path, fragment) = split_fragment(&nav.content);
-    let file = html_to_md.get(&content_path)?;
-    let mut link = path_to_markdown_link(file);
-    if let Some(fragment) = fragment {
-        link.push('#');
-        link.push_str(&fragment);
-    }
-    let mut md = format!("{}- [{}]({})\n", "  ".repeat(indent), nav.label, link);
+    let warning_idx = warnings.len();
+    let mut children_md = String::new();
     for child in &nav.children {
-        if let Some(child_md) = epub_nav_to_md(child, indent + 1, html_to_md) {
-            md.push_str(&child_md);
+        if let Some(child_md) = epub_nav_to_md(child, indent + 1, html_to_md, warnings) {
+            children_md.push_str(&child_md);
         }
     }
-    Some(md)
+    let link = if let Some(file) = html_to_md.get(&content_path) {
+        let mut link = path_to_markdown_link(file);
+        if let Some(fragment) = fragment {
+            link.push('#');
+            link.push_str(&percent_encode(&fragment));
+        }
+        escape_link_destination(&link)
+    } else {
+        let label = nav.label.clone();
+        let target = nav.content.display().to_string();
+        if children_md.is_empty() {
+            warnings.insert(warning_idx, Warning::DroppedTocEntry { label, target });
+            return None;
+        }
+        // Keep the structure with a draft chapter, so the children stay nested.
+        warnings.insert(warning_idx, Warning::DraftChapter { label, target });
+        String::new()
+    };
+    Some(format!(
+        "{}- [{}]({})\n{}",
+        "  ".repeat(indent),
+        escape_link_text(&nav.label),
+        link,
+        children_md
+    ))
 }
 
 /// generate SUMMARY.md and the file mapping from html to md
@@ -81,38 +159,217 @@ fn epub_nav_to_md(
 ///
 /// * `summary_md` - The SUMMARY.md content
 /// * `html_to_md` - The file mapping from html to md
+///
+/// The TOC links are normalized to the chapter paths like in a conversion, on a
+/// copy of the TOC. As the chapters can't be read from `epub_doc`, chapters left out
+/// of the TOC are titled after their file name rather than their headings.
 pub fn generate_summary_md<R: Read + Seek>(
     epub_doc: &EpubDoc<R>,
 ) -> (String, HashMap<PathBuf, PathBuf>) {
-    let title = epub_doc.get_title();
-    let mut summary_md = if let Some(title) = title {
-        format!("# {}\n\n", title)
+    let html_to_md = collect_html_to_md(epub_doc);
+    let mut toc = epub_doc.toc.clone();
+    normalize_toc_links(&mut toc, &html_to_md);
+    let summary_md = render_summary_md(
+        epub_doc,
+        &toc,
+        &html_to_md,
+        &collect_toc_titles(&toc),
+        &HashSet::new(),
+        &ConvertOptions::default(),
+        &mut Vec::new(),
+    );
+    (summary_md, html_to_md)
+}
+
+/// SUMMARY.md of a book with the chapter mappings it was rendered from
+struct BookSummary {
+    summary_md: String,
+    html_to_md: HashMap<PathBuf, PathBuf>,
+    html_to_title: HashMap<PathBuf, String>,
+}
+
+/// Map the chapters to Markdown files and titles, and render SUMMARY.md from the
+/// TOC, or the spine if there is none
+fn summarize_book<R: Read + Seek>(
+    epub_doc: &mut EpubDoc<R>,
+    options: &ConvertOptions,
+    warnings: &mut Vec<Warning>,
+) -> Result<BookSummary, Error> {
+    let html_to_md = collect_html_to_md(epub_doc);
+    normalize_toc_links(&mut epub_doc.toc, &html_to_md);
+    let part_title_pages = if options.part_headers {
+        collect_part_title_pages(epub_doc, &html_to_md)?
     } else {
-        "".to_string()
+        HashSet::new()
     };
-    let html_to_md = epub_doc
+    let html_to_title = collect_chapter_titles(epub_doc, &html_to_md)?;
+    let summary_md = render_summary_md(
+        epub_doc,
+        &epub_doc.toc,
+        &html_to_md,
+        &html_to_title,
+        &part_title_pages,
+        options,
+        warnings,
+    );
+    Ok(BookSummary {
+        summary_md,
+        html_to_md,
+        html_to_title,
+    })
+}
+
+fn collect_html_to_md<R: Read + Seek>(epub_doc: &EpubDoc<R>) -> HashMap<PathBuf, PathBuf> {
+    epub_doc
         .resources
         .iter()
         .filter(|(_, resource)| {
             ["application/xhtml+xml", "text/html"].contains(&resource.mime.as_str())
         })
         .map(|(_, resource)| (resource.path.clone(), resource.path.with_extension("md")))
-        .collect::<HashMap<PathBuf, PathBuf>>();
-    if epub_doc.toc.is_empty() {
-        summary_md.push_str(&spine_to_md(epub_doc, &html_to_md));
+        .collect()
+}
+
+fn render_summary_md<R: Read + Seek>(
+    epub_doc: &EpubDoc<R>,
+    toc: &[NavPoint],
+    html_to_md: &HashMap<PathBuf, PathBuf>,
+    html_to_title: &HashMap<PathBuf, String>,
+    part_title_pages: &HashSet<PathBuf>,
+    options: &ConvertOptions,
+    warnings: &mut Vec<Warning>,
+) -> String {
+    let chapters_md = if toc.is_empty() {
+        spine_to_md(epub_doc, html_to_md, html_to_title)
     } else {
-        for nav in &epub_doc.toc {
-            if let Some(md) = epub_nav_to_md(nav, 0, &html_to_md) {
-                summary_md.push_str(&md);
+        toc_to_md(toc, html_to_md, part_title_pages, options, warnings)
+    };
+    let title = match epub_doc.get_title() {
+        Some(title) => Some(title),
+        // Without a title, mdBook would take the first part header as the summary title.
+        None if chapters_md.starts_with("# ") => Some("Summary".to_string()),
+        None => None,
+    };
+    match title {
+        Some(title) => format!("# {}\n\n{}", title, chapters_md),
+        None => chapters_md,
+    }
+}
+
+fn toc_to_md(
+    toc: &[NavPoint],
+    html_to_md: &HashMap<PathBuf, PathBuf>,
+    part_title_pages: &HashSet<PathBuf>,
+    options: &ConvertOptions,
+    warnings: &mut Vec<Warning>,
+) -> String {
+    let mut md = String::new();
+    let mut in_part = false;
+    for nav in toc {
+        if options.part_headers && is_part_nav(nav, html_to_md, part_title_pages) {
+            push_blank_line(&mut md);
+            md.push_str(&format!("# {}\n\n", normalize_whitespace(&nav.label)));
+            for child in &nav.children {
+                if let Some(child_md) = epub_nav_to_md(child, 0, html_to_md, warnings) {
+                    md.push_str(&child_md);
+                }
+            }
+            in_part = true;
+        } else if let Some(nav_md) = epub_nav_to_md(nav, 0, html_to_md, warnings) {
+            if in_part {
+                // Keep standalone chapters after a part visually apart from it.
+                push_blank_line(&mut md);
+                md.push_str("---\n\n");
+                in_part = false;
             }
+            md.push_str(&nav_md);
         }
     }
-    (summary_md, html_to_md)
+    md
+}
+
+fn push_blank_line(md: &mut String) {
+    if !md.is_empty() && !md.ends_with("\n\n") {
+        md.push('\n');
+    }
+}
+
+/// A top-level TOC entry becomes a part header when it has children and either
+/// points to nothing convertible or to a page holding only the part title.
+fn is_part_nav(
+    nav: &NavPoint,
+    html_to_md: &HashMap<PathBuf, PathBuf>,
+    part_title_pages: &HashSet<PathBuf>,
+) -> bool {
+    if nav.children.is_empty() || nav.label.trim().is_empty() {
+        return false;
+    }
+    let (content_path, fragment) = split_fragment(&nav.content);
+    if !html_to_md.contains_key(&content_path) {
+        return true;
+    }
+    fragment.is_none() && part_title_pages.contains(&content_path)
+}
+
+fn collect_part_title_pages<R: Read + Seek>(
+    epub_doc: &mut EpubDoc<R>,
+    html_to_md: &HashMap<PathBuf, PathBuf>,
+) -> Result<HashSet<PathBuf>, Error> {
+    let candidates = epub_doc
+        .toc
+        .iter()
+        .filter(|nav| !nav.children.is_empty())
+        .map(|nav| strip_fragment(&nav.content))
+        .filter(|path| html_to_md.contains_key(path))
+        .collect::<HashSet<_>>();
+    // Title pages in the book's language then get no `<div lang>` wrapper
+    let context = ChapterContext {
+        language: book_language(epub_doc),
+        ..Default::default()
+    };
+    let mut part_title_pages = HashSet::new();
+    for path in candidates {
+        let Some(content) = epub_doc.get_resource_by_path(&path) else {
+            continue;
+        };
+        let html = String::from_utf8(content)?;
+        if is_part_title_page(&convert_epub_html_to_md(&html, &context)?) {
+            part_title_pages.insert(path);
+        }
+    }
+    Ok(part_title_pages)
+}
+
+/// A part title page holds nothing but headings, anchors and decorative images,
+/// possibly in a `<div lang>` wrapper.
+fn is_part_title_page(markdown: &str) -> bool {
+    markdown
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .all(|line| {
+            is_atx_heading(line)
+                || is_html_anchor(line)
+                || is_markdown_image(line)
+                || is_lang_wrapper(line)
+        })
+}
+
+fn is_lang_wrapper(line: &str) -> bool {
+    line == "</div>"
+        || (line.starts_with("<div ")
+            && line.ends_with('>')
+            && (line.contains(" lang=") || line.contains(" dir=")))
+}
+
+fn is_markdown_image(line: &str) -> bool {
+    line.starts_with("![") && line.ends_with(')')
 }
 
 fn spine_to_md<R: Read + Seek>(
     epub_doc: &EpubDoc<R>,
     html_to_md: &HashMap<PathBuf, PathBuf>,
+    html_to_title: &HashMap<PathBuf, String>,
 ) -> String {
     let mut md = String::new();
     for spine_item in &epub_doc.spine {
@@ -125,10 +382,14 @@ fn spine_to_md<R: Read + Seek>(
         let Some(file) = html_to_md.get(&resource.path) else {
             continue;
         };
+        let title = html_to_title
+            .get(&resource.path)
+            .cloned()
+            .unwrap_or_else(|| path_to_title(&resource.path));
         md.push_str(&format!(
             "- [{}]({})\n",
-            path_to_title(&resource.path),
-            path_to_markdown_link(file)
+            escape_link_text(&title),
+            escape_link_destination(&path_to_markdown_link(file))
         ));
     }
     md
@@ -139,7 +400,23 @@ fn extract_chapters_and_resources<R: Read + Seek>(
     output_dir: impl AsRef<Path>,
     html_to_md: &HashMap<PathBuf, PathBuf>,
     html_to_title: &HashMap<PathBuf, String>,
-) -> Result<(), Error> {
+    options: &ConvertOptions,
+    locale: &BookLocale,
+    features: &BookFeatures,
+) -> Result<(HashMap<PathBuf, String>, Vec<Warning>), Error> {
+    let mut chapters = HashMap::new();
+    let mut broken_links = Vec::new();
+    let html_to_depth = collect_toc_depths(&epub_doc.toc);
+    let stylesheet = Arc::new(collect_stylesheet(epub_doc));
+    let mut referenced_ids = collect_referenced_ids(epub_doc, html_to_md)?;
+    let shared_html_to_md = Arc::new(html_to_md.clone());
+    let resource_paths = Arc::new(
+        epub_doc
+            .resources
+            .values()
+            .map(|resource| resource.path.clone())
+            .collect::<HashSet<_>>(),
+    );
     let src_dir = output_dir.as_ref().join("src");
     for (_, resource) in epub_doc.resources.clone() {
         let path = &resource.path;
@@ -150,10 +427,32 @@ fn extract_chapters_and_resources<R: Read + Seek>(
         let target_path = if let Some(md_path) = html_to_md.get(path) {
             // html file, convert to md
             let html = String::from_utf8(content.clone())?;
-            let markdown = convert_epub_html_to_md(&html)?;
-            let markdown =
-                add_missing_chapter_title(&markdown, html_to_title.get(path).map(String::as_str));
-            content = post_process_md(&markdown, path, html_to_md).into_bytes();
+            let toc_depth = html_to_depth.get(path).copied().unwrap_or_default();
+            let context = ChapterContext {
+                html_path: path.clone(),
+                html_to_md: shared_html_to_md.clone(),
+                resource_paths: resource_paths.clone(),
+                referenced_ids: referenced_ids.remove(path).unwrap_or_default(),
+                ..ChapterContext::new(options, md_path, toc_depth, locale, &stylesheet, features)
+            };
+            let markdown = convert_epub_html_to_md(&html, &context)?;
+            let markdown = add_missing_chapter_title(
+                &markdown,
+                html_to_title.get(path).map(String::as_str),
+                context.top_heading_level.unwrap_or(1),
+            );
+            let links = context.broken_links.lock().expect("unreachable");
+            broken_links.push((
+                md_path.clone(),
+                broken_link_warnings(md_path, &markdown, &links),
+            ));
+            chapters.insert(md_path.clone(), markdown.clone());
+            content = markdown.into_bytes();
+            let md_dir = src_dir.join(md_path.parent().unwrap_or_else(|| Path::new("")));
+            for svg in context.extracted_svgs.lock().expect("unreachable").iter() {
+                fs::create_dir_all(&md_dir)?;
+                fs::write(md_dir.join(&svg.file_name), &svg.content)?;
+            }
             if md_path == Path::new("SUMMARY.md") {
                 src_dir.join("_SUMMARY.md")
             } else {
@@ -169,37 +468,141 @@ fn extract_chapters_and_resources<R: Read + Seek>(
         }
         fs::write(target_path, content)?;
     }
-    Ok(())
+    broken_links.sort_by(|(left, _), (right, _)| left.cmp(right));
+    let broken_links = broken_links
+        .into_iter()
+        .flat_map(|(_, warnings)| warnings)
+        .collect();
+    Ok((chapters, broken_links))
 }
 
-fn collect_chapter_titles<R: Read + Seek>(
-    epub_doc: &EpubDoc<R>,
+/// Match the `href` attributes of an HTML document
+static HREF: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"\bhref\s*=\s*(?:"(?P<double>[^"]*)"|'(?P<single>[^']*)')"#).expect("unreachable")
+});
+
+/// Ids each chapter has some link or TOC entry pointing to
+fn collect_referenced_ids<R: Read + Seek>(
+    epub_doc: &mut EpubDoc<R>,
     html_to_md: &HashMap<PathBuf, PathBuf>,
-) -> HashMap<PathBuf, String> {
-    let mut html_to_title = HashMap::new();
+) -> Result<HashMap<PathBuf, HashSet<String>>, Error> {
+    fn collect_nav(nav: &NavPoint, referenced_ids: &mut HashMap<PathBuf, HashSet<String>>) {
+        if let (path, Some(fragment)) = split_fragment(&nav.content) {
+            referenced_ids.entry(path).or_default().insert(fragment);
+        }
+        for child in &nav.children {
+            collect_nav(child, referenced_ids);
+        }
+    }
+    let mut referenced_ids = HashMap::<PathBuf, HashSet<String>>::new();
     for nav in &epub_doc.toc {
-        collect_nav_titles(nav, &mut html_to_title);
+        collect_nav(nav, &mut referenced_ids);
     }
-    for spine_item in &epub_doc.spine {
-        let Some(resource) = epub_doc.resources.get(&spine_item.idref) else {
+    for html_path in html_to_md.keys() {
+        let Some(content) = epub_doc.get_resource_by_path(html_path) else {
+            continue;
+        };
+        let html = String::from_utf8(content)?;
+        for caps in HREF.captures_iter(&html) {
+            let href = caps
+                .name("double")
+                .or_else(|| caps.name("single"))
+                .map_or("", |href| href.as_str());
+            if URL_LINK.is_match(href) {
+                continue;
+            }
+            let (link, Some(fragment)) = split_link(href) else {
+                continue;
+            };
+            if fragment.is_empty() {
+                continue;
+            }
+            let target = if link.as_os_str().is_empty() {
+                html_path.clone()
+            } else {
+                let path = resolve_relative_path(html_path, &link.to_string_lossy());
+                find_chapter(html_to_md, &path).map_or(path, |(path, _)| path.clone())
+            };
+            referenced_ids.entry(target).or_default().insert(fragment);
+        }
+    }
+    Ok(referenced_ids)
+}
+
+/// Rules of all the stylesheets of the book, ordered by path for stable output
+fn collect_stylesheet<R: Read + Seek>(epub_doc: &mut EpubDoc<R>) -> Stylesheet {
+    let mut stylesheet = Stylesheet::default();
+    let mut paths = epub_doc
+        .resources
+        .values()
+        .filter(|resource| resource.mime == "text/css")
+        .map(|resource| resource.path.clone())
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        if let Some(css) = epub_doc.get_resource_str_by_path(path) {
+            stylesheet.add(&css);
+        }
+    }
+    stylesheet
+}
+
+fn collect_chapter_titles<R: Read + Seek>(
+    epub_doc: &mut EpubDoc<R>,
+    html_to_md: &HashMap<PathBuf, PathBuf>,
+) -> Result<HashMap<PathBuf, String>, Error> {
+    let mut html_to_title = collect_toc_titles(&epub_doc.toc);
+    for spine_item in epub_doc.spine.clone() {
+        let Some(path) = epub_doc
+            .resources
+            .get(&spine_item.idref)
+            .map(|resource| resource.path.clone())
+        else {
             continue;
         };
-        if html_to_md.contains_key(&resource.path) {
-            html_to_title
-                .entry(resource.path.clone())
-                .or_insert_with(|| path_to_title(&resource.path));
+        if !html_to_md.contains_key(&path) || html_to_title.contains_key(&path) {
+            continue;
         }
+        let title = match epub_doc.get_resource_by_path(&path) {
+            Some(content) => content_title(&String::from_utf8(content)?)?,
+            None => None,
+        };
+        let title = title.unwrap_or_else(|| path_to_title(&path));
+        html_to_title.insert(path, title);
+    }
+    Ok(html_to_title)
+}
+
+/// Titles of the documents the TOC points to, from their labels
+fn collect_toc_titles(toc: &[NavPoint]) -> HashMap<PathBuf, String> {
+    let mut html_to_title = HashMap::new();
+    for nav in toc {
+        collect_nav_titles(nav, &mut html_to_title);
     }
     html_to_title
 }
 
+/// Title of a document taken from its content: the first `<h1>`-`<h3>`,
+/// then the `<title>` in `<head>`
+fn content_title(html: &str) -> io::Result<Option<String>> {
+    let tree = htmd::HtmlToMarkdown::new().html_to_tree(html)?;
+    let title = [&["h1", "h2", "h3"][..], &["title"]]
+        .into_iter()
+        .find_map(|tags| {
+            dom::find_first(&tree, &|node| {
+                dom::element_name(node).is_some_and(|name| tags.contains(&name))
+                    && !dom::text_content(node).is_empty()
+            })
+        })
+        .map(|node| dom::text_content(&node));
+    Ok(title)
+}
+
 fn collect_nav_titles(nav: &NavPoint, html_to_title: &mut HashMap<PathBuf, String>) {
-    let label = nav.label.trim();
+    let label = normalize_whitespace(&nav.label);
     if !label.is_empty() {
         let path = strip_fragment(&nav.content);
-        html_to_title
-            .entry(path)
-            .or_insert_with(|| label.to_string());
+        html_to_title.entry(path).or_insert(label);
     }
 
     for child in &nav.children {
@@ -207,56 +610,337 @@ fn collect_nav_titles(nav: &NavPoint, html_to_title: &mut HashMap<PathBuf, Strin
     }
 }
 
-fn convert_epub_html_to_md(html: &str) -> io::Result<String> {
-    htmd::HtmlToMarkdown::builder()
+/// Shallowest TOC nesting depth each document appears at
+fn collect_toc_depths(toc: &[NavPoint]) -> HashMap<PathBuf, usize> {
+    fn collect(navs: &[NavPoint], depth: usize, html_to_depth: &mut HashMap<PathBuf, usize>) {
+        for nav in navs {
+            let entry = html_to_depth
+                .entry(strip_fragment(&nav.content))
+                .or_insert(depth);
+            *entry = (*entry).min(depth);
+            collect(&nav.children, depth + 1, html_to_depth);
+        }
+    }
+    let mut html_to_depth = HashMap::new();
+    collect(toc, 0, &mut html_to_depth);
+    html_to_depth
+}
+
+/// Settings for converting the HTML of a single chapter to Markdown
+#[derive(Debug, Clone, Default)]
+struct ChapterContext {
+    /// Path of the chapter in the EPUB, which its links are relative to
+    html_path: PathBuf,
+    /// Markdown file each chapter of the book becomes, to rewrite links with
+    html_to_md: Arc<HashMap<PathBuf, PathBuf>>,
+    /// Paths of every file of the book, to tell broken links from links to images
+    /// and other files
+    resource_paths: Arc<HashSet<PathBuf>>,
+    /// What to do with links to files the book doesn't have
+    broken_link_mode: BrokenLinkMode,
+    /// Links of the chapter to files the book doesn't have, in document order
+    broken_links: Arc<Mutex<Vec<BrokenLink>>>,
+    /// Level the top heading of the chapter is moved to, `None` keeps heading levels as is
+    top_heading_level: Option<usize>,
+    /// Demote every top heading after the first one
+    demote_extra_h1: bool,
+    /// How callouts are converted, `None` keeps them as plain paragraphs
+    admonition_style: Option<AdmonitionStyle>,
+    /// Extra `class → admonition kind` mappings
+    admonition_classes: HashMap<String, String>,
+    /// What to do with inline `<svg>` elements
+    svg_mode: SvgMode,
+    /// Prefix for the names of SVG files extracted from the chapter
+    svg_file_prefix: String,
+    /// SVGs extracted from the chapter, to be written next to it
+    extracted_svgs: Arc<Mutex<Vec<ExtractedSvg>>>,
+    /// How `<ruby>` annotations are carried over
+    ruby_mode: RubyMode,
+    /// Render spans with a small-caps class in small caps
+    small_caps: bool,
+    /// Extra small-caps classes
+    small_caps_classes: Vec<String>,
+    /// Write figure captions as `<figcaption>` HTML
+    figcaption_html: bool,
+    /// Extra verse classes
+    verse_classes: Vec<String>,
+    /// Ids in the chapter some link or TOC entry points to
+    referenced_ids: HashSet<String>,
+    /// Keep an anchor for every id, not only the referenced ones
+    keep_all_anchors: bool,
+    /// Rules of the book's stylesheets, to infer formatting from classes
+    stylesheet: Arc<Stylesheet>,
+    /// Language of the book, so only passages in other languages need a wrapper
+    language: Option<String>,
+    /// The book reads right to left, so only `dir="ltr"` passages need a wrapper
+    right_to_left: bool,
+    /// Where the handlers record the features the chapter uses
+    features: BookFeatures,
+}
+
+/// Features the converted chapters use, which have to be enabled in book.toml.
+/// Clones share the same flags, so handlers of every chapter can set them.
+#[derive(Debug, Clone, Default)]
+struct BookFeatures {
+    math: Arc<AtomicBool>,
+    admonish: Arc<AtomicBool>,
+}
+
+/// Language, reading direction and writing mode of the book
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct BookLocale {
+    /// Language tag from the metadata, e.g. `en-US`
+    language: Option<String>,
+    /// The text runs right to left, as in Arabic or Hebrew
+    right_to_left: bool,
+    /// The text is set in vertical lines, as in many Japanese books
+    vertical_writing: bool,
+}
+
+/// Capture the `page-progression-direction` of the spine in the OPF file
+static PAGE_PROGRESSION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"<(?:[\w-]+:)?spine\b[^>]*\bpage-progression-direction\s*=\s*["']([^"']*)["']"#)
+        .expect("unreachable")
+});
+/// Match a vertical `writing-mode`, `-epub-writing-mode` or `-webkit-writing-mode`
+static VERTICAL_WRITING_MODE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"writing-mode\s*:\s*vertical-(?:rl|lr)").expect("unreachable"));
+
+impl BookLocale {
+    fn detect<R: Read + Seek>(epub_doc: &mut EpubDoc<R>) -> Self {
+        let language = book_language(epub_doc);
+        let progression = epub_doc
+            .get_resource_str_by_path(epub_doc.root_file.clone())
+            .and_then(|opf| {
+                PAGE_PROGRESSION
+                    .captures(&opf)
+                    .map(|caps| caps[1].trim().to_ascii_lowercase())
+            });
+        let stylesheets = epub_doc
+            .resources
+            .values()
+            .filter(|resource| resource.mime == "text/css")
+            .map(|resource| resource.path.clone())
+            .collect::<Vec<_>>();
+        let vertical_css = stylesheets.into_iter().any(|path| {
+            epub_doc
+                .get_resource_str_by_path(path)
+                .is_some_and(|css| VERTICAL_WRITING_MODE.is_match(&css))
+        });
+        Self::new(language.as_deref(), progression.as_deref(), vertical_css)
+    }
+
+    fn new(language: Option<&str>, progression: Option<&str>, vertical_css: bool) -> Self {
+        let primary = language
+            .and_then(|language| language.split(['-', '_']).next())
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        // CJK books progress right to left because their lines are vertical, while
+        // the text itself still isn't right-to-left
+        let cjk = matches!(
+            primary.as_str(),
+            "ja" | "jpn" | "zh" | "zho" | "chi" | "ko" | "kor"
+        );
+        let rtl_progression = progression == Some("rtl");
+        Self {
+            language: language.map(str::to_string),
+            right_to_left: TextDirection::from_lang_code(&primary) == TextDirection::RightToLeft
+                || (rtl_progression && !cjk),
+            vertical_writing: vertical_css || (rtl_progression && cjk),
+        }
+    }
+}
+
+impl ChapterContext {
+    fn new(
+        options: &ConvertOptions,
+        md_path: &Path,
+        toc_depth: usize,
+        locale: &BookLocale,
+        stylesheet: &Arc<Stylesheet>,
+        features: &BookFeatures,
+    ) -> Self {
+        let top_heading_level = options.normalize_headings.then(|| {
+            if options.toc_depth_heading_offset {
+                (toc_depth + 1).min(6)
+            } else {
+                1
+            }
+        });
+        Self {
+            html_path: PathBuf::new(),
+            html_to_md: Arc::default(),
+            resource_paths: Arc::default(),
+            broken_link_mode: options.broken_links,
+            broken_links: Arc::default(),
+            referenced_ids: HashSet::new(),
+            top_heading_level,
+            demote_extra_h1: options.demote_extra_h1,
+            admonition_style: options.admonition_style,
+            admonition_classes: options.admonition_classes.clone(),
+            svg_mode: options.svg_mode,
+            svg_file_prefix: svg_file_prefix(md_path),
+            extracted_svgs: Arc::default(),
+            ruby_mode: options.ruby_mode,
+            small_caps: options.small_caps,
+            small_caps_classes: options.small_caps_classes.clone(),
+            figcaption_html: options.figcaption_html,
+            verse_classes: options.verse_classes.clone(),
+            keep_all_anchors: options.keep_all_anchors,
+            stylesheet: stylesheet.clone(),
+            language: locale.language.clone(),
+            right_to_left: locale.right_to_left,
+            features: features.clone(),
+        }
+    }
+}
+
+/// `chapter-1.md` extracts its SVGs to `chapter-1-svg-1.svg`, `chapter-1-svg-2.svg`…
+fn svg_file_prefix(md_path: &Path) -> String {
+    let stem = md_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    format!("{stem}-svg")
+}
+
+fn convert_epub_html_to_md(html: &str, context: &ChapterContext) -> io::Result<String> {
+    let tree = htmd::HtmlToMarkdown::new().html_to_tree(html)?;
+    find_broken_links(&tree, context);
+    rewrite_links(&tree, &context.html_path, &context.html_to_md);
+    let anchors = Arc::new(Anchors {
+        referenced_ids: context.referenced_ids.clone(),
+        keep_all: context.keep_all_anchors,
+    });
+    let mut builder = htmd::HtmlToMarkdown::builder()
         .skip_tags(vec!["head"])
         .add_handler(
-            vec![
-                "a",
-                "article",
-                "aside",
-                "blockquote",
-                "body",
-                "div",
-                "figcaption",
-                "figure",
-                "h1",
-                "h2",
-                "h3",
-                "h4",
-                "h5",
-                "h6",
-                "li",
-                "main",
-                "nav",
-                "p",
-                "section",
-                "span",
-                "td",
-                "th",
-            ],
-            preserve_id_handler,
+            vec!["table"],
+            TableHandler {
+                anchors: anchors.clone(),
+            },
         )
-        .build()
-        .convert(html)
-}
-
-fn preserve_id_handler(handlers: &dyn Handlers, element: htmd::Element) -> Option<HandlerResult> {
-    let id = element
-        .attrs
-        .iter()
-        .find(|attr| &*attr.name.local == "id")
-        .map(|attr| attr.value.to_string())
-        .filter(|id| !id.trim().is_empty());
-    let mut result = handlers.fallback(element)?;
-    if let Some(id) = id {
-        let content = result.content.trim_start_matches('\n');
-        result.content = format!("\n\n<a id=\"{}\"></a>\n\n{}", escape_attr(&id), content);
+        .add_handler(
+            vec!["pre"],
+            CodeHandler {
+                anchors: anchors.clone(),
+            },
+        )
+        .add_handler(
+            vec!["math"],
+            MathHandler {
+                found_math: context.features.math.clone(),
+            },
+        )
+        .add_handler(
+            vec!["svg"],
+            SvgHandler {
+                mode: context.svg_mode,
+                file_prefix: context.svg_file_prefix.clone(),
+                extracted: context.extracted_svgs.clone(),
+                anchors: anchors.clone(),
+            },
+        );
+    builder = match context.ruby_mode {
+        RubyMode::Html => builder.add_handler(vec!["ruby"], ruby_html_handler),
+        RubyMode::Parenthesized => builder.add_handler(vec!["ruby"], ruby_text_handler),
+    };
+    builder = builder
+        .add_handler(
+            vec!["dl"],
+            DefinitionListHandler {
+                anchors: anchors.clone(),
+            },
+        )
+        .add_handler(
+            vec!["abbr", "kbd", "mark", "sub", "sup"],
+            inline_html_handler,
+        );
+    builder = builder.add_handler(
+        vec!["figure"],
+        FigureHandler {
+            caption_html: context.figcaption_html,
+            anchors: anchors.clone(),
+        },
+    );
+    builder = builder.add_handler(
+        vec!["blockquote", "div", "p", "section"],
+        VerseHandler::new(&context.verse_classes, anchors.clone()),
+    );
+    if context.small_caps {
+        builder = builder.add_handler(
+            vec!["span"],
+            SmallCapsHandler::new(&context.small_caps_classes),
+        );
+    }
+    builder = builder.add_handler(
+        vec!["div", "p", "span"],
+        StyleHandler {
+            stylesheet: context.stylesheet.clone(),
+        },
+    );
+    builder = builder.add_handler(
+        vec![
+            "abbr",
+            "article",
+            "aside",
+            "b",
+            "bdi",
+            "bdo",
+            "blockquote",
+            "body",
+            "cite",
+            "div",
+            "em",
+            "h1",
+            "h2",
+            "h3",
+            "h4",
+            "h5",
+            "h6",
+            "i",
+            "p",
+            "q",
+            "section",
+            "span",
+            "strong",
+        ],
+        LangHandler {
+            book_language: context.language.clone(),
+            book_direction: if context.right_to_left { "rtl" } else { "ltr" },
+        },
+    );
+    if let Some(style) = context.admonition_style {
+        builder = builder.add_handler(
+            vec!["aside", "blockquote", "div", "p", "section"],
+            AdmonitionHandler::new(
+                style,
+                &context.admonition_classes,
+                context.features.admonish.clone(),
+            ),
+        );
     }
-    Some(result)
+    builder = builder.add_handler(AnchorHandler::tags(), AnchorHandler { anchors });
+    if let Some(top_level) = context.top_heading_level {
+        builder = builder.add_handler(
+            vec!["h1", "h2", "h3", "h4", "h5", "h6"],
+            HeadingLevels::new(&tree, top_level, context.demote_extra_h1),
+        );
+    }
+    Ok(builder.build().tree_to_markdown(&tree))
 }
 
-fn add_missing_chapter_title(markdown: &str, title: Option<&str>) -> String {
+fn add_missing_chapter_title(markdown: &str, title: Option<&str>, level: usize) -> String {
     let title = match title.map(str::trim).filter(|title| !title.is_empty()) {
         Some(title) => title,
         None => return markdown.to_string(),
@@ -265,11 +949,12 @@ fn add_missing_chapter_title(markdown: &str, title: Option<&str>) -> String {
         return markdown.to_string();
     }
 
+    let hashes = "#".repeat(level);
     let markdown = markdown.trim_start_matches('\n');
     if markdown.is_empty() {
-        format!("# {title}")
+        format!("{hashes} {title}")
     } else {
-        format!("# {title}\n\n{markdown}")
+        format!("{hashes} {title}\n\n{markdown}")
     }
 }
 
@@ -305,6 +990,87 @@ fn is_html_anchor(line: &str) -> bool {
         && (trimmed.contains(" id=") || trimmed.contains(" name="))
 }
 
+/// Point the TOC entries to the chapter paths of the manifest, decoded and
+/// without query strings, so they can be looked up in `html_to_md`
+fn normalize_toc_links(toc: &mut [NavPoint], html_to_md: &HashMap<PathBuf, PathBuf>) {
+    for nav in toc {
+        let (path, fragment) = split_link(&nav.content.to_string_lossy());
+        let path = find_chapter(html_to_md, &path).map_or(path, |(path, _)| path.clone());
+        nav.content = match fragment {
+            Some(fragment) => PathBuf::from(format!("{}#{fragment}", path.display())),
+            None => path,
+        };
+        normalize_toc_links(&mut nav.children, html_to_md);
+    }
+}
+
+/// Split a link into its path and fragment, dropping any query string and
+/// decoding percent-encoded characters, eg `ch%201.xhtml?x=1#s` into `ch 1.xhtml`
+/// and `s`
+fn split_link(link: &str) -> (PathBuf, Option<String>) {
+    let (link, fragment) = match link.split_once('#') {
+        Some((link, fragment)) => (link, Some(percent_decode(fragment))),
+        None => (link, None),
+    };
+    let link = link.split_once('?').map_or(link, |(link, _)| link);
+    (PathBuf::from(percent_decode(link)), fragment)
+}
+
+/// The chapter a path points to and the Markdown file it becomes. Books are not
+/// always consistent about the case of file names, so a path matching a single
+/// chapter but for the case will do.
+fn find_chapter<'a>(
+    html_to_md: &'a HashMap<PathBuf, PathBuf>,
+    path: &Path,
+) -> Option<(&'a PathBuf, &'a PathBuf)> {
+    if let Some(chapter) = html_to_md.get_key_value(path) {
+        return Some(chapter);
+    }
+    let lowercase = path.to_string_lossy().to_lowercase();
+    let mut matches = html_to_md
+        .iter()
+        .filter(|(html_path, _)| html_path.to_string_lossy().to_lowercase() == lowercase);
+    let chapter = matches.next()?;
+    matches.next().is_none().then_some(chapter)
+}
+
+/// Decode `%XX` escapes, keeping the text as is if they don't decode to UTF-8
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).unwrap_or_else(|_| text.to_string())
+}
+
+/// Encode the characters that can't appear as is in a link path or fragment
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for c in text.chars() {
+        if c.is_ascii_control() || " \"#%<>?[\\]^`{|}()".contains(c) {
+            encoded.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            encoded.push(c);
+        }
+    }
+    encoded
+}
+
 fn strip_fragment(path: &Path) -> PathBuf {
     split_fragment(path).0
 }
@@ -397,6 +1163,41 @@ fn path_to_markdown_link(path: &Path) -> String {
     parts.join("/")
 }
 
+fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Escape the text of a Markdown link or image, so brackets and backslashes can't
+/// end it early
+fn escape_link_text(label: &str) -> String {
+    let mut escaped = String::new();
+    for c in normalize_whitespace(label).chars() {
+        if matches!(c, '\\' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escape a Markdown link destination. Spaces become `%20`, which mdBook decodes
+/// back in SUMMARY.md, and characters that would end the destination are
+/// backslash-escaped.
+fn escape_link_destination(link: &str) -> String {
+    let mut escaped = String::new();
+    for c in link.chars() {
+        match c {
+            ' ' => escaped.push_str("%20"),
+            '\\' | '(' | ')' | '<' | '>' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 fn escape_attr(value: &str) -> String {
     value
         .replace('&', "&amp;")
@@ -405,14 +1206,17 @@ fn escape_attr(value: &str) -> String {
         .replace('>', "&gt;")
 }
 
-/// Capture the `{link}` without `#`, eg:
-/// ```text
-/// [ABC]({abc.html}#xxx)
-/// [ABC]({abc.html})
-/// ```
-static LINK: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r#"\[[^\]]+\]\((?P<link>[^#)]+)(?P<fragment>#[^)]+)?\)"#).expect("unreachable")
-});
+/// Backticks to fence `text` as code with, longer than any run of backticks in it
+/// and at least `min_len` long
+fn backtick_fence(text: &str, min_len: usize) -> String {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    "`".repeat((longest_run + 1).max(min_len))
+}
+
 /// Match the URL link, eg:
 /// ```text
 /// https://www.example.com\
@@ -420,106 +1224,432 @@ static LINK: LazyLock<Regex> = LazyLock::new(|| {
 static URL_LINK: LazyLock<Regex> =
     LazyLock::new(|| Regex::new(r"^[a-z][a-z0-9+.-]*:").expect("unreachable"));
 
-fn post_process_md(
-    markdown: &str,
+/// Point the `href`s of a chapter to the Markdown files the linked chapters
+/// become, before it is converted, so every link form htmd writes picks them up, as
+/// does the raw HTML of tables and SVGs. SVG's `xlink:href` has the local name
+/// `href` too. mdBook only maps Markdown links to pages, so `src`s of frames and
+/// objects showing a chapter point to its `.html` page instead.
+fn rewrite_links(
+    tree: &Rc<htmd::Node>,
     current_html_path: &Path,
     html_to_md: &HashMap<PathBuf, PathBuf>,
-) -> String {
-    LINK.replace_all(markdown, |caps: &Captures| {
-        // replace [ABC](abc.html#xxx) to [ABC](abc.md#xxx)
-        let origin = &caps[0];
-        let link = &caps["link"];
-        // Don't modify links with schemes like `https`.
-        if URL_LINK.is_match(link) {
-            return origin.to_string();
-        }
-        let resolved_path = resolve_relative_path(current_html_path, link);
-        if let Some(md_path) = html_to_md.get(&resolved_path) {
-            let current_md_path = html_to_md
-                .get(current_html_path)
-                .cloned()
-                .unwrap_or_else(|| current_html_path.with_extension("md"));
-            let replacement = path_to_markdown_link(&relative_path(&current_md_path, md_path));
-            origin.replace(link, &replacement)
-        } else {
-            origin.to_string()
-        }
-    })
-    .to_string()
+) {
+    dom::rewrite_attrs(tree, &["href"], &|href| {
+        rewrite_link(href, current_html_path, html_to_md, "md")
+    });
+    dom::rewrite_attrs(tree, &["src"], &|src| {
+        rewrite_link(src, current_html_path, html_to_md, "html")
+    });
 }
 
-fn write_book_toml<R: Read + Seek>(
-    epub_doc: &EpubDoc<R>,
-    output_dir: impl AsRef<Path>,
-) -> io::Result<()> {
-    let output_dir = output_dir.as_ref();
-    let title = epub_doc.get_title();
-    let authors = epub_doc
-        .metadata
-        .iter()
-        .filter(|m| m.property == "creator")
-        .map(|m| m.value.clone())
-        .collect::<Vec<_>>();
-    let description = epub_doc
-        .mdata("description")
-        .and_then(|m| htmd::convert(&m.value).ok());
-    let lang = epub_doc
-        .mdata("language")
-        .or_else(|| epub_doc.mdata("lang"))
-        .map(|m| m.value.clone());
-    let mut config = BookConfig::default();
-    config.title = title;
-    config.authors = authors;
-    config.description = description;
-    config.src = PathBuf::from("src");
-    config.language = lang;
-    let toml_content = format!("[book]\n{}", toml::to_string(&config).expect("unreachable"));
-    fs::write(output_dir.join("book.toml"), toml_content)?;
-    Ok(())
+/// Replace `abc.xhtml#xxx` with `abc.md#xxx`, or another `extension`, relative to
+/// the current chapter, `None` for links to anything but a chapter
+fn rewrite_link(
+    href: &str,
+    current_html_path: &Path,
+    html_to_md: &HashMap<PathBuf, PathBuf>,
+    extension: &str,
+) -> Option<String> {
+    // Don't modify links with schemes like `https`, or to the current chapter.
+    if URL_LINK.is_match(href) {
+        return None;
+    }
+    let (link, fragment) = split_link(href);
+    if link.as_os_str().is_empty() {
+        return None;
+    }
+    let resolved_path = resolve_relative_path(current_html_path, &link.to_string_lossy());
+    let (_, md_path) = find_chapter(html_to_md, &resolved_path)?;
+    let current_md_path = html_to_md
+        .get(current_html_path)
+        .cloned()
+        .unwrap_or_else(|| current_html_path.with_extension("md"));
+    let target_path = md_path.with_extension(extension);
+    Some(encode_link(
+        &relative_path(&current_md_path, &target_path),
+        fragment.as_deref(),
+    ))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    #[test]
-    fn test_replace_links() {
-        let markdown = r"[hello](hello.html#xxx) [hi](hi.xhtml)";
-        let html_to_md = HashMap::from([
-            (
-                PathBuf::from("text/current.xhtml"),
-                PathBuf::from("text/current.md"),
-            ),
-            (
-                PathBuf::from("text/hello.html"),
-                PathBuf::from("text/hello.md"),
-            ),
-            (PathBuf::from("text/hi.xhtml"), PathBuf::from("text/hi.md")),
-        ]);
+/// Link to a relative path with an optional fragment, percent-encoding each path
+/// segment but keeping the `/`s
+fn encode_link(relative: &Path, fragment: Option<&str>) -> String {
+    let mut link = path_to_markdown_link(relative)
+        .split('/')
+        .map(percent_encode)
+        .collect::<Vec<_>>()
+        .join("/");
+    if let Some(fragment) = fragment {
+        link.push('#');
+        link.push_str(&percent_encode(fragment));
+    }
+    link
+}
 
-        let markdown = post_process_md(markdown, Path::new("text/current.xhtml"), &html_to_md);
+/// A link to a file the book doesn't have, and what was done with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BrokenLink {
+    /// The link as it appears in the EPUB
+    target: String,
+    /// Text of the link, to find it in the Markdown output
+    text: String,
+    fix: BrokenLinkFix,
+}
 
-        assert_eq!(markdown, "[hello](hello.md#xxx) [hi](hi.md)");
-    }
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BrokenLinkFix {
+    Kept,
+    Unlinked,
+    /// Pointed to the chapter that becomes this Markdown file, relative to the
+    /// current one
+    Resolved(String),
+}
+
+/// Record the links of a chapter to files the book doesn't have, before the
+/// others are rewritten, and unlink them or resolve them by file stem if asked to
+fn find_broken_links(tree: &Rc<htmd::Node>, context: &ChapterContext) {
+    let mut broken_links = context.broken_links.lock().expect("unreachable");
+    for node in dom::descendants(tree) {
+        if dom::element_name(&node) != Some("a") {
+            continue;
+        }
+        let Some(href) = dom::attr(&node, "href") else {
+            continue;
+        };
+        if URL_LINK.is_match(&href) {
+            continue;
+        }
+        let (link, fragment) = split_link(&href);
+        if link.as_os_str().is_empty() {
+            continue;
+        }
+        let path = resolve_relative_path(&context.html_path, &link.to_string_lossy());
+        if find_chapter(&context.html_to_md, &path).is_some()
+            || context.resource_paths.contains(&path)
+        {
+            continue;
+        }
+        let fix = match context.broken_link_mode {
+            BrokenLinkMode::Keep => BrokenLinkFix::Kept,
+            BrokenLinkMode::Unlink => {
+                dom::remove_attr(&node, "href");
+                BrokenLinkFix::Unlinked
+            }
+            BrokenLinkMode::Fuzzy => match find_chapter_by_stem(&context.html_to_md, &path) {
+                Some(chapter) => {
+                    let resolved = encode_link(
+                        &relative_path(&context.html_path, chapter),
+                        fragment.as_deref(),
+                    );
+                    dom::set_attr(&node, "href", &resolved);
+                    let md_path = &context.html_to_md[chapter];
+                    BrokenLinkFix::Resolved(path_to_markdown_link(&relative_path(
+                        &context.html_path,
+                        md_path,
+                    )))
+                }
+                None => BrokenLinkFix::Kept,
+            },
+        };
+        broken_links.push(BrokenLink {
+            target: href,
+            text: normalize_whitespace(&dom::text_content(&node)),
+            fix,
+        });
+    }
+}
+
+/// The only chapter with the same file stem as `path`, ignoring case
+fn find_chapter_by_stem<'a>(
+    html_to_md: &'a HashMap<PathBuf, PathBuf>,
+    path: &Path,
+) -> Option<&'a PathBuf> {
+    let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+    let mut matches = html_to_md.keys().filter(|html_path| {
+        html_path
+            .file_stem()
+            .is_some_and(|html_stem| html_stem.to_string_lossy().to_lowercase() == stem)
+    });
+    let chapter = matches.next()?;
+    matches.next().is_none().then_some(chapter)
+}
+
+/// Warnings for the broken links of a chapter. Links are found in the Markdown
+/// output by their text, in order, and otherwise get the line of the previous one.
+fn broken_link_warnings(md_path: &Path, markdown: &str, links: &[BrokenLink]) -> Vec<Warning> {
+    let lines = markdown.lines().collect::<Vec<_>>();
+    let mut line = 0;
+    let mut warnings = Vec::new();
+    for link in links {
+        let needle = if link.text.is_empty() {
+            &link.target
+        } else {
+            &link.text
+        };
+        if let Some(offset) = lines[line.min(lines.len())..]
+            .iter()
+            .position(|text| text.contains(needle.as_str()))
+        {
+            line += offset;
+        }
+        let chapter = path_to_markdown_link(md_path);
+        let target = link.target.clone();
+        warnings.push(match &link.fix {
+            BrokenLinkFix::Kept => Warning::BrokenLink {
+                chapter,
+                line: line + 1,
+                target,
+            },
+            BrokenLinkFix::Unlinked => Warning::UnlinkedBrokenLink {
+                chapter,
+                line: line + 1,
+                target,
+            },
+            BrokenLinkFix::Resolved(resolved) => Warning::ResolvedBrokenLink {
+                chapter,
+                line: line + 1,
+                target,
+                resolved: resolved.clone(),
+            },
+        });
+    }
+    warnings
+}
+
+/// Styles for books written vertically, added to book.toml as additional CSS
+const VERTICAL_WRITING_CSS: &str = "\
+/* The book is written vertically, with lines running right to left */
+.content main {
+    writing-mode: vertical-rl;
+    max-width: none;
+    height: calc(100vh - var(--menu-bar-height) - 4em);
+    overflow-x: auto;
+}
+
+.content main pre,
+.content main table {
+    writing-mode: horizontal-tb;
+}
+";
+
+fn book_language<R: Read + Seek>(epub_doc: &EpubDoc<R>) -> Option<String> {
+    epub_doc
+        .mdata("language")
+        .or_else(|| epub_doc.mdata("lang"))
+        .map(|m| m.value.clone())
+}
+
+/// Match a Markdown link with a fragment, like `[x](chapter.md#id)` or `[x](#id)`
+static FRAGMENT_LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\]\((?P<path>[^()\s#]*)#(?P<fragment>[^()\s]+)\)").expect("unreachable")
+});
+/// Match a raw HTML link with a fragment, like `<a href="chapter.md#id">` in a table
+/// kept as HTML
+static HTML_FRAGMENT_LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r##"\bhref\s*=\s*"(?P<path>[^"#\s]*)#(?P<fragment>[^"\s]+)""##).expect("unreachable")
+});
+/// Capture the `id` attributes in raw HTML, like those of the anchors in front of
+/// elements
+static ID_ATTR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"\bid\s*=\s*"(?P<id>[^"]*)""#).expect("unreachable"));
+
+/// Warn about links between chapters, in Markdown or raw HTML, whose fragment
+/// matches neither an anchor nor a heading id mdBook generates
+fn check_fragment_links(chapters: &HashMap<PathBuf, String>) -> Vec<Warning> {
+    let anchors = chapters
+        .iter()
+        .map(|(md_path, markdown)| (md_path, markdown_anchors(markdown)))
+        .collect::<HashMap<_, _>>();
+    let mut md_paths = chapters.keys().collect::<Vec<_>>();
+    md_paths.sort();
+    let mut warnings = Vec::new();
+    for md_path in md_paths {
+        for (i, line) in chapters[md_path].lines().enumerate() {
+            let links = FRAGMENT_LINK
+                .captures_iter(line)
+                .chain(HTML_FRAGMENT_LINK.captures_iter(line));
+            for caps in links {
+                let link = &caps["path"];
+                if URL_LINK.is_match(link) {
+                    continue;
+                }
+                let target_path = if link.is_empty() {
+                    md_path.clone()
+                } else {
+                    resolve_relative_path(md_path, &percent_decode(link))
+                };
+                let Some(target_anchors) = anchors.get(&target_path) else {
+                    continue;
+                };
+                if !target_anchors.contains(&percent_decode(&caps["fragment"])) {
+                    warnings.push(Warning::MissingAnchor {
+                        chapter: path_to_markdown_link(md_path),
+                        line: i + 1,
+                        target: format!("{link}#{}", &caps["fragment"]),
+                    });
+                }
+            }
+        }
+    }
+    warnings
+}
+
+/// Ids a link can point to in a chapter: those in raw HTML and the ones mdBook
+/// generates for headings
+fn markdown_anchors(markdown: &str) -> HashSet<String> {
+    let mut anchors = ID_ATTR
+        .captures_iter(markdown)
+        .map(|caps| caps["id"].to_string())
+        .collect::<HashSet<_>>();
+    let mut heading_ids = HashMap::<String, usize>::new();
+    for line in markdown.lines().filter(|line| is_atx_heading(line)) {
+        let text = line.trim().trim_start_matches('#').trim_end_matches('#');
+        let id = heading_id(text);
+        // mdBook numbers repeated heading ids, e.g. `notes`, `notes-1`
+        let count = heading_ids.entry(id.clone()).or_default();
+        if *count == 0 {
+            anchors.insert(id);
+        } else {
+            anchors.insert(format!("{id}-{count}"));
+        }
+        *count += 1;
+    }
+    anchors
+}
+
+/// The id mdBook gives a heading with this text, where inline HTML doesn't count
+fn heading_id(text: &str) -> String {
+    let mut in_tag = false;
+    text.trim()
+        .chars()
+        .filter(|&c| {
+            match c {
+                '<' => in_tag = true,
+                '>' if in_tag => {
+                    in_tag = false;
+                    return false;
+                }
+                _ => {}
+            }
+            !in_tag
+        })
+        .filter_map(|c| {
+            if c.is_alphanumeric() || c == '_' || c == '-' {
+                Some(c.to_ascii_lowercase())
+            } else if c.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn write_book_toml<R: Read + Seek>(
+    epub_doc: &EpubDoc<R>,
+    output_dir: impl AsRef<Path>,
+    options: &ConvertOptions,
+    locale: &BookLocale,
+    features: &BookFeatures,
+) -> io::Result<()> {
+    let output_dir = output_dir.as_ref();
+    let title = epub_doc.get_title();
+    let authors = epub_doc
+        .metadata
+        .iter()
+        .filter(|m| m.property == "creator")
+        .map(|m| m.value.clone())
+        .collect::<Vec<_>>();
+    let description = epub_doc
+        .mdata("description")
+        .and_then(|m| htmd::convert(&m.value).ok());
+    let mut config = BookConfig::default();
+    config.title = title;
+    config.authors = authors;
+    config.description = description;
+    config.src = PathBuf::from("src");
+    config.language = locale.language.clone();
+    if locale.right_to_left {
+        config.text_direction = Some(TextDirection::RightToLeft);
+    }
+    let mut toml_content = format!("[book]\n{}", toml::to_string(&config).expect("unreachable"));
+    let mut html_config = toml::Table::new();
+    if features.math.load(Ordering::Relaxed) {
+        html_config.insert("mathjax-support".to_string(), true.into());
+    }
+    if options.vertical_writing_css && locale.vertical_writing {
+        fs::write(
+            output_dir.join("vertical-writing.css"),
+            VERTICAL_WRITING_CSS,
+        )?;
+        html_config.insert(
+            "additional-css".to_string(),
+            vec!["vertical-writing.css"].into(),
+        );
+    }
+    if !html_config.is_empty() {
+        toml_content.push_str(&format!(
+            "\n[output.html]\n{}",
+            toml::to_string(&html_config).expect("unreachable")
+        ));
+    }
+    if features.admonish.load(Ordering::Relaxed) {
+        toml_content.push_str("\n[preprocessor.admonish]\ncommand = \"mdbook-admonish\"\n");
+    }
+    fs::write(output_dir.join("book.toml"), toml_content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_replace_links() {
+        let html = r#"<p><a href="hello.html#xxx">hello</a> <a href="hi.xhtml">hi</a></p>"#;
+        let context = ChapterContext {
+            html_path: PathBuf::from("text/current.xhtml"),
+            html_to_md: Arc::new(HashMap::from([
+                (
+                    PathBuf::from("text/current.xhtml"),
+                    PathBuf::from("text/current.md"),
+                ),
+                (
+                    PathBuf::from("text/hello.html"),
+                    PathBuf::from("text/hello.md"),
+                ),
+                (PathBuf::from("text/hi.xhtml"), PathBuf::from("text/hi.md")),
+            ])),
+            ..Default::default()
+        };
+
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
+
+        assert_eq!(markdown, "[hello](hello.md#xxx) [hi](hi.md)");
+    }
 
     #[test]
     fn test_replace_links_resolves_relative_paths() {
-        let markdown = r"[next](../part2/index.xhtml#target) [same](chapter.xhtml) [site](https://example.com/index.xhtml)";
-        let html_to_md = HashMap::from([
-            (
-                PathBuf::from("OPS/part1/current.xhtml"),
-                PathBuf::from("OPS/part1/current.md"),
-            ),
-            (
-                PathBuf::from("OPS/part1/chapter.xhtml"),
-                PathBuf::from("OPS/part1/chapter.md"),
-            ),
-            (
-                PathBuf::from("OPS/part2/index.xhtml"),
-                PathBuf::from("OPS/part2/index.md"),
-            ),
-        ]);
+        let html = r#"<a href="../part2/index.xhtml#target">next</a> <a href="chapter.xhtml">same</a> <a href="https://example.com/index.xhtml">site</a>"#;
+        let context = ChapterContext {
+            html_path: PathBuf::from("OPS/part1/current.xhtml"),
+            html_to_md: Arc::new(HashMap::from([
+                (
+                    PathBuf::from("OPS/part1/current.xhtml"),
+                    PathBuf::from("OPS/part1/current.md"),
+                ),
+                (
+                    PathBuf::from("OPS/part1/chapter.xhtml"),
+                    PathBuf::from("OPS/part1/chapter.md"),
+                ),
+                (
+                    PathBuf::from("OPS/part2/index.xhtml"),
+                    PathBuf::from("OPS/part2/index.md"),
+                ),
+            ])),
+            ..Default::default()
+        };
 
-        let markdown = post_process_md(markdown, Path::new("OPS/part1/current.xhtml"), &html_to_md);
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
 
         assert_eq!(
             markdown,
@@ -527,6 +1657,180 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_replace_links_with_any_link_text() {
+        let html = r#"<p><a href="notes.xhtml#n1">see [1]</a> <a href="notes.xhtml"><img src="../images/n.png" alt="Notes"/></a> <a href="notes.xhtml">two
+            lines</a></p><table><tr><td rowspan="2"><a href="notes.xhtml#n2">raw</a></td></tr></table>"#;
+        let context = ChapterContext {
+            html_path: PathBuf::from("text/current.xhtml"),
+            html_to_md: Arc::new(HashMap::from([(
+                PathBuf::from("text/notes.xhtml"),
+                PathBuf::from("text/notes.md"),
+            )])),
+            ..Default::default()
+        };
+
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
+
+        assert_eq!(
+            markdown,
+            "[see \\[1\\]](notes.md#n1) [![Notes](../images/n.png)](notes.md) [two lines](notes.md)\n\n\
+             <table><tbody>\n<tr><td rowspan=\"2\"><a href=\"notes.md#n2\">raw</a></td></tr></tbody>\n</table>"
+        );
+    }
+
+    #[test]
+    fn test_replace_encoded_links() {
+        let html = r#"<p><a href="chapter%201.xhtml?x=1#s%C3%A9">one</a> <a href="./text/CH2.XHTML">two</a> <a href="chapter%201.xhtml#a%20b">three</a></p>"#;
+        let context = ChapterContext {
+            html_path: PathBuf::from("OEBPS/Text/current.xhtml"),
+            html_to_md: Arc::new(HashMap::from([
+                (
+                    PathBuf::from("OEBPS/Text/chapter 1.xhtml"),
+                    PathBuf::from("OEBPS/Text/chapter 1.md"),
+                ),
+                (
+                    PathBuf::from("OEBPS/Text/Text/ch2.xhtml"),
+                    PathBuf::from("OEBPS/Text/Text/ch2.md"),
+                ),
+            ])),
+            ..Default::default()
+        };
+
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
+
+        assert_eq!(
+            markdown,
+            "[one](chapter%201.md#sé) [two](Text/ch2.md) [three](chapter%201.md#a%20b)"
+        );
+    }
+
+    #[test]
+    fn test_normalize_toc_links() {
+        let html_to_md = HashMap::from([(
+            PathBuf::from("OEBPS/Text/chapter 1.xhtml"),
+            PathBuf::from("OEBPS/Text/chapter 1.md"),
+        )]);
+        let mut toc = vec![NavPoint {
+            label: "One".to_string(),
+            content: PathBuf::from("OEBPS/text/chapter%201.xhtml?x=1#s%201"),
+            children: vec![],
+            play_order: Some(1),
+        }];
+
+        normalize_toc_links(&mut toc, &html_to_md);
+        let mut warnings = Vec::new();
+
+        assert_eq!(
+            toc[0].content,
+            PathBuf::from("OEBPS/Text/chapter 1.xhtml#s 1")
+        );
+        assert_eq!(
+            epub_nav_to_md(&toc[0], 0, &html_to_md, &mut warnings).unwrap(),
+            "- [One](OEBPS/Text/chapter%201.md#s%201)\n"
+        );
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_broken_links() {
+        let html = r#"<p>Intro</p><p>See <a href="missing.xhtml#n1">the notes</a> and <a href="../images/a.png">a picture</a>.</p><p><a href="../Text/Chapter02.html#s">Next</a></p>"#;
+        let convert = |mode| {
+            let context = ChapterContext {
+                html_path: PathBuf::from("OEBPS/text/ch01.xhtml"),
+                html_to_md: Arc::new(HashMap::from([
+                    (
+                        PathBuf::from("OEBPS/text/ch01.xhtml"),
+                        PathBuf::from("OEBPS/text/ch01.md"),
+                    ),
+                    (
+                        PathBuf::from("OEBPS/text/chapter02.xhtml"),
+                        PathBuf::from("OEBPS/text/chapter02.md"),
+                    ),
+                ])),
+                resource_paths: Arc::new(HashSet::from([PathBuf::from("OEBPS/images/a.png")])),
+                broken_link_mode: mode,
+                ..Default::default()
+            };
+            let markdown = convert_epub_html_to_md(html, &context).unwrap();
+            let links = context.broken_links.lock().unwrap();
+            let warnings = broken_link_warnings(Path::new("OEBPS/text/ch01.md"), &markdown, &links);
+            (markdown, warnings)
+        };
+
+        let (markdown, warnings) = convert(BrokenLinkMode::Keep);
+        assert_eq!(
+            markdown,
+            "Intro\n\nSee [the notes](missing.xhtml#n1) and [a picture](../images/a.png).\n\n[Next](../Text/Chapter02.html#s)"
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                Warning::BrokenLink {
+                    chapter: "OEBPS/text/ch01.md".to_string(),
+                    line: 3,
+                    target: "missing.xhtml#n1".to_string(),
+                },
+                Warning::BrokenLink {
+                    chapter: "OEBPS/text/ch01.md".to_string(),
+                    line: 5,
+                    target: "../Text/Chapter02.html#s".to_string(),
+                },
+            ]
+        );
+
+        let (markdown, warnings) = convert(BrokenLinkMode::Unlink);
+        assert_eq!(
+            markdown,
+            "Intro\n\nSee the notes and [a picture](../images/a.png).\n\nNext"
+        );
+        assert!(matches!(
+            &warnings[..],
+            [
+                Warning::UnlinkedBrokenLink { line: 3, .. },
+                Warning::UnlinkedBrokenLink { line: 5, .. }
+            ]
+        ));
+
+        let (markdown, warnings) = convert(BrokenLinkMode::Fuzzy);
+        assert_eq!(
+            markdown,
+            "Intro\n\nSee [the notes](missing.xhtml#n1) and [a picture](../images/a.png).\n\n[Next](chapter02.md#s)"
+        );
+        assert_eq!(
+            warnings[1],
+            Warning::ResolvedBrokenLink {
+                chapter: "OEBPS/text/ch01.md".to_string(),
+                line: 5,
+                target: "../Text/Chapter02.html#s".to_string(),
+                resolved: "chapter02.md".to_string(),
+            }
+        );
+        assert!(matches!(warnings[0], Warning::BrokenLink { line: 3, .. }));
+    }
+
+    #[test]
+    fn test_replace_links_in_raw_html() {
+        let html = r#"<table><tr><td colspan="2"><a href="notes.xhtml#n1">note</a> <img src="../images/n.png" alt="n"/><iframe src="notes.xhtml#n3"></iframe></td></tr></table>
+            <svg><a xlink:href="notes.xhtml#n2"><text>2</text></a></svg>"#;
+        let context = ChapterContext {
+            html_path: PathBuf::from("text/current.xhtml"),
+            html_to_md: Arc::new(HashMap::from([(
+                PathBuf::from("text/notes.xhtml"),
+                PathBuf::from("text/notes.md"),
+            )])),
+            ..Default::default()
+        };
+
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
+
+        assert!(markdown.contains(r#"<a href="notes.md#n1">note</a>"#));
+        assert!(markdown.contains(r#"src="../images/n.png""#));
+        assert!(markdown.contains(r#"<a href="notes.md#n2"><text>2</text></a>"#));
+        assert!(markdown.contains(r#"<iframe src="notes.html#n3"></iframe>"#));
+        assert!(!markdown.contains("xhtml"));
+    }
+
     #[test]
     fn test_nav_fragment_is_preserved_in_summary() {
         let nav = NavPoint {
@@ -540,11 +1844,161 @@ mod tests {
             PathBuf::from("epub/text/chapter.md"),
         )]);
 
-        let markdown = epub_nav_to_md(&nav, 0, &html_to_md).unwrap();
+        let markdown = epub_nav_to_md(&nav, 0, &html_to_md, &mut Vec::new()).unwrap();
 
         assert_eq!(markdown, "- [Section I](epub/text/chapter.md#section-1)\n");
     }
 
+    #[test]
+    fn test_summary_labels_and_links_are_escaped() {
+        let nav = NavPoint {
+            label: "See [1]\n   and C:\\temp".to_string(),
+            content: PathBuf::from("text/my chapter (1).xhtml#part-1"),
+            children: Vec::new(),
+            play_order: None,
+        };
+        let html_to_md = HashMap::from([(
+            PathBuf::from("text/my chapter (1).xhtml"),
+            PathBuf::from("text/my chapter (1).md"),
+        )]);
+
+        let markdown = epub_nav_to_md(&nav, 0, &html_to_md, &mut Vec::new()).unwrap();
+
+        assert_eq!(
+            markdown,
+            "- [See \\[1\\] and C:\\\\temp](text/my%20chapter%20\\(1\\).md#part-1)\n"
+        );
+    }
+
+    #[test]
+    fn test_missing_nav_target_becomes_draft_chapter() {
+        let nav = NavPoint {
+            label: "Part One".to_string(),
+            content: PathBuf::from("text/missing.xhtml"),
+            children: vec![
+                NavPoint {
+                    label: "Chapter 1".to_string(),
+                    content: PathBuf::from("text/ch1.xhtml"),
+                    children: Vec::new(),
+                    play_order: None,
+                },
+                NavPoint {
+                    label: "Notes".to_string(),
+                    content: PathBuf::from("text/notes.xhtml"),
+                    children: Vec::new(),
+                    play_order: None,
+                },
+            ],
+            play_order: None,
+        };
+        let html_to_md = HashMap::from([(
+            PathBuf::from("text/ch1.xhtml"),
+            PathBuf::from("text/ch1.md"),
+        )]);
+        let mut warnings = Vec::new();
+
+        let markdown = epub_nav_to_md(&nav, 0, &html_to_md, &mut warnings).unwrap();
+
+        assert_eq!(markdown, "- [Part One]()\n  - [Chapter 1](text/ch1.md)\n");
+        assert_eq!(
+            warnings,
+            vec![
+                Warning::DraftChapter {
+                    label: "Part One".to_string(),
+                    target: "text/missing.xhtml".to_string(),
+                },
+                Warning::DroppedTocEntry {
+                    label: "Notes".to_string(),
+                    target: "text/notes.xhtml".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_part_headers_in_summary() {
+        let nav = |label: &str, content: &str, children: Vec<NavPoint>| NavPoint {
+            label: label.to_string(),
+            content: PathBuf::from(content),
+            children,
+            play_order: None,
+        };
+        let toc = vec![
+            nav(
+                "Part One",
+                "text/part1.xhtml",
+                vec![nav("Chapter 1", "text/ch1.xhtml", Vec::new())],
+            ),
+            nav(
+                "Part Two",
+                "text/missing.xhtml",
+                vec![nav("Chapter 2", "text/ch2.xhtml", Vec::new())],
+            ),
+            nav("Appendix", "text/appendix.xhtml", Vec::new()),
+        ];
+        let html_to_md = ["part1", "ch1", "ch2", "appendix"]
+            .into_iter()
+            .map(|name| {
+                (
+                    PathBuf::from(format!("text/{name}.xhtml")),
+                    PathBuf::from(format!("text/{name}.md")),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let part_title_pages = HashSet::from([PathBuf::from("text/part1.xhtml")]);
+        let options = ConvertOptions {
+            part_headers: true,
+            ..Default::default()
+        };
+
+        let markdown = toc_to_md(
+            &toc,
+            &html_to_md,
+            &part_title_pages,
+            &options,
+            &mut Vec::new(),
+        );
+
+        assert_eq!(
+            markdown,
+            "# Part One\n\n- [Chapter 1](text/ch1.md)\n\n\
+             # Part Two\n\n- [Chapter 2](text/ch2.md)\n\n\
+             ---\n\n- [Appendix](text/appendix.md)\n"
+        );
+    }
+
+    #[test]
+    fn test_part_title_page_detection() {
+        let title_page = r#"<html><body><section id="part-1"><h1>Part One</h1><img src="ornament.png" alt=""/></section></body></html>"#;
+        let chapter = r#"<html><body><h1>Part One</h1><p>Opening paragraph.</p></body></html>"#;
+
+        assert!(is_part_title_page(
+            &convert_epub_html_to_md(title_page, &ChapterContext::default()).unwrap()
+        ));
+        assert!(!is_part_title_page(
+            &convert_epub_html_to_md(chapter, &ChapterContext::default()).unwrap()
+        ));
+
+        let english = ChapterContext {
+            language: Some("en".to_string()),
+            ..Default::default()
+        };
+        let with_lang = r#"<html xml:lang="en-US"><body><h1>Part One</h1></body></html>"#;
+        let other_lang = r#"<html lang="eng"><body><h1>Part One</h1><img src="ornament.png" alt=""/></body></html>"#;
+        let chapter_with_lang =
+            r#"<html lang="fr"><body><h1>Partie un</h1><p>Premier paragraphe.</p></body></html>"#;
+
+        assert!(is_part_title_page(
+            &convert_epub_html_to_md(with_lang, &english).unwrap()
+        ));
+        assert!(is_part_title_page(
+            &convert_epub_html_to_md(other_lang, &english).unwrap()
+        ));
+        assert!(!is_part_title_page(
+            &convert_epub_html_to_md(chapter_with_lang, &english).unwrap()
+        ));
+    }
+
     #[test]
     fn test_epub_html_conversion_skips_head_metadata() {
         let html = r#"
@@ -564,8 +2018,8 @@ mod tests {
         "#;
         let title = "A Scandal in Bohemia".to_string();
 
-        let markdown = convert_epub_html_to_md(html).unwrap();
-        let markdown = add_missing_chapter_title(&markdown, Some(&title));
+        let markdown = convert_epub_html_to_md(html, &ChapterContext::default()).unwrap();
+        let markdown = add_missing_chapter_title(&markdown, Some(&title), 1);
 
         assert_eq!(
             markdown,
@@ -585,11 +2039,563 @@ mod tests {
                 </body>
             </html>
         "#;
+        let context = ChapterContext {
+            referenced_ids: HashSet::from(["chapter-1".to_string()]),
+            ..Default::default()
+        };
 
-        let markdown = convert_epub_html_to_md(html).unwrap();
-        let markdown = add_missing_chapter_title(&markdown, Some("Chapter One"));
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
+        let markdown = add_missing_chapter_title(&markdown, Some("Chapter One"), 1);
 
         assert!(markdown.starts_with("<a id=\"chapter-1\"></a>\n\n## Chapter One"));
+        assert!(
+            convert_epub_html_to_md(html, &ChapterContext::default())
+                .unwrap()
+                .starts_with("## Chapter One")
+        );
+    }
+
+    #[test]
+    fn test_content_title_fallbacks() {
+        let with_heading = r#"
+            <html>
+                <head><title>Head Title</title></head>
+                <body>
+                    <p class="epigraph">An epigraph.</p>
+                    <h2>The <em>Real</em>
+                        Title</h2>
+                    <h1>Later Heading</h1>
+                </body>
+            </html>
+        "#;
+        let with_head_title =
+            r#"<html><head><title> Head Title </title></head><body><p>Text.</p></body></html>"#;
+        let untitled = r#"<html><body><p>Text.</p></body></html>"#;
+
+        assert_eq!(
+            content_title(with_heading).unwrap().as_deref(),
+            Some("The Real Title")
+        );
+        assert_eq!(
+            content_title(with_head_title).unwrap().as_deref(),
+            Some("Head Title")
+        );
+        assert_eq!(content_title(untitled).unwrap(), None);
+    }
+
+    #[test]
+    fn test_heading_levels_are_normalized() {
+        let html = r#"
+            <html>
+                <body>
+                    <h3 id="start">Chapter One</h3>
+                    <h4>Section</h4>
+                    <h3>Another Top Heading</h3>
+                </body>
+            </html>
+        "#;
+        let context = ChapterContext {
+            top_heading_level: Some(2),
+            demote_extra_h1: true,
+            keep_all_anchors: true,
+            ..Default::default()
+        };
+
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
+
+        assert_eq!(
+            markdown,
+            "<a id=\"start\"></a>\n\n## Chapter One\n\n### Section\n\n### Another Top Heading"
+        );
+
+        let html = "<h1>A</h1><h2>a1</h2><h1>B</h1><h2>b1</h2>";
+        let context = ChapterContext {
+            top_heading_level: Some(1),
+            demote_extra_h1: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "# A\n\n## a1\n\n## B\n\n### b1"
+        );
+    }
+
+    #[test]
+    fn test_simple_table_becomes_gfm() {
+        let html = r#"
+            <table id="t1" class="calibre7">
+                <caption>Table 1: Results</caption>
+                <tr><th>Name</th><th>Value</th></tr>
+                <tr><td>a|b</td><td><p>1</p></td></tr>
+                <tr><td>line<br/>break</td></tr>
+            </table>
+        "#;
+        let context = ChapterContext {
+            keep_all_anchors: true,
+            ..Default::default()
+        };
+
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
+
+        assert_eq!(
+            markdown,
+            "<a id=\"t1\"></a>\n\nTable 1: Results\n\n\
+             | Name | Value |\n| --- | --- |\n| a\\|b | 1 |\n| line<br>break |  |"
+        );
+    }
+
+    #[test]
+    fn test_spanning_table_falls_back_to_html() {
+        let html = r#"
+            <table>
+                <tbody>
+                    <tr><td class="c1" colspan="2" style="color: red">Wide</td></tr>
+                    <tr><td>A</td><td><ul><li>B</li></ul></td></tr>
+                    <tr><td><pre>a
+    b
+
+c</pre></td><td><textarea>  d  </textarea></td></tr>
+                </tbody>
+            </table>
+        "#;
+
+        let markdown = convert_epub_html_to_md(html, &ChapterContext::default()).unwrap();
+
+        assert_eq!(
+            markdown,
+            "<table> <tbody>\n<tr><td colspan=\"2\">Wide</td></tr>\n\
+             <tr><td>A</td><td><ul><li>B</li></ul></td></tr>\n\
+             <tr><td><pre>a&#10;    b&#10;&#10;c</pre></td><td><textarea>  d  </textarea></td></tr> </tbody>\n</table>"
+        );
+    }
+
+    #[test]
+    fn test_code_listing_keeps_language_and_whitespace() {
+        let html = r##"<pre data-type="programlisting" data-code-language="rust">fn main() {
+    <strong id="line-2">println!</strong>("hi");  <a class="co" id="co_1" href="#callout_1"><img src="callouts/1.png" alt="1"/></a>
+}</pre>
+<pre><code class="language-python">def f():
+	return `x`<i class="conum" data-value="2"></i><b>(2)</b></code></pre>"##;
+        let context = ChapterContext {
+            referenced_ids: HashSet::from(["co_1".to_string(), "line-2".to_string()]),
+            ..Default::default()
+        };
+
+        let markdown = convert_epub_html_to_md(html, &ChapterContext::default()).unwrap();
+
+        assert_eq!(
+            markdown,
+            "```rust\nfn main() {\n    println!(\"hi\");  // (1)\n}\n```\n\n\
+             ```python\ndef f():\n\treturn `x` # (2)\n```"
+        );
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "<a id=\"line-2\"></a><a id=\"co_1\"></a>\n\n\
+             ```rust\nfn main() {\n    println!(\"hi\");  // (1)\n}\n```\n\n\
+             ```python\ndef f():\n\treturn `x` # (2)\n```"
+        );
+    }
+
+    #[test]
+    fn test_mathml_becomes_latex() {
+        let html = r#"
+            <p>Energy is <math><mi>E</mi><mo>=</mo><mi>m</mi><msup><mi>c</mi><mn>2</mn></msup></math>.</p>
+            <math display="block">
+                <mrow>
+                    <munderover><mo>∑</mo><mrow><mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></munderover>
+                    <msub><mi>x</mi><mi>i</mi></msub><mo>≤</mo><mfrac><mi>α</mi><msqrt><mn>2</mn></msqrt></mfrac>
+                </mrow>
+            </math>
+            <p><math><mtext>50% of {a_b} & $x^2~\</mtext></math></p>
+        "#;
+        let context = ChapterContext::default();
+
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
+
+        assert_eq!(
+            markdown,
+            "Energy is \\\\( E=m{c}^{2} \\\\).\n\n\
+             \\\\[ {\\\\sum}\\_{i=1}^{n}{x}\\_{i}\\\\le\\\\frac{\\\\alpha}{\\\\sqrt{2}} \\\\]\n\n\
+             \\\\( \\\\text{50\\\\% of \\\\{a\\\\\\_b\\\\} \\\\& \\\\$x\\\\^{}2\\\\~{}\\\\textbackslash{}} \\\\)"
+        );
+        assert!(context.features.math.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_callouts_become_admonitions() {
+        let html = r#"
+            <div class="note calibre3"><h6>Note</h6><p>Plain note.</p></div>
+            <aside data-type="warning"><h5>Data loss</h5><p>Back up first.</p></aside>
+            <div class="sidebar"><p>Aside.</p></div>
+        "#;
+        let context = ChapterContext {
+            admonition_style: Some(AdmonitionStyle::Admonish),
+            admonition_classes: HashMap::from([("Sidebar".to_string(), "info".to_string())]),
+            ..Default::default()
+        };
+
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
+
+        assert_eq!(
+            markdown,
+            "```admonish note\nPlain note.\n```\n\n\
+             ```admonish warning title=\"Data loss\"\nBack up first.\n```\n\n\
+             ```admonish info\nAside.\n```"
+        );
+        assert!(context.features.admonish.load(Ordering::Relaxed));
+
+        let context = ChapterContext {
+            admonition_style: Some(AdmonitionStyle::GitHub),
+            ..Default::default()
+        };
+
+        let markdown = convert_epub_html_to_md(html, &context).unwrap();
+
+        assert_eq!(
+            markdown,
+            "> [!NOTE]\n> Plain note.\n\n\
+             > [!WARNING]\n> **Data loss**\n>\n> Back up first.\n\n\
+             Aside."
+        );
+    }
+
+    #[test]
+    fn test_svg_handling() {
+        let html = r#"
+            <div><svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" id="cover" viewBox="0 0 600 800"><title>Cover</title><image width="600" height="800" xlink:href="../images/cover.jpg"/></svg></div>
+            <p><svg viewBox="0 0 10 10"><circle r="5"/></svg></p>
+        "#;
+        let inline = ChapterContext::default();
+        let extract = ChapterContext {
+            svg_mode: SvgMode::Extract,
+            svg_file_prefix: "ch1-svg".to_string(),
+            referenced_ids: HashSet::from(["cover".to_string()]),
+            ..Default::default()
+        };
+        let unwrap = ChapterContext {
+            svg_mode: SvgMode::Unwrap,
+            referenced_ids: HashSet::from(["cover".to_string()]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &inline).unwrap(),
+            "<svg id=\"cover\" viewBox=\"0 0 600 800\"><title>Cover</title>\
+             <image width=\"600\" height=\"800\" href=\"../images/cover.jpg\"></image></svg>\n\n\
+             <svg viewBox=\"0 0 10 10\"><circle r=\"5\"></circle></svg>"
+        );
+        assert_eq!(
+            convert_epub_html_to_md(html, &extract).unwrap(),
+            "<a id=\"cover\"></a>![Cover](ch1-svg-1.svg)\n\n![](ch1-svg-2.svg)"
+        );
+        assert_eq!(
+            extract.extracted_svgs.lock().unwrap()[1],
+            ExtractedSvg {
+                file_name: "ch1-svg-2.svg".to_string(),
+                content: "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 10 10\">\
+                          <circle r=\"5\"></circle></svg>\n"
+                    .to_string(),
+            }
+        );
+        assert_eq!(
+            convert_epub_html_to_md(html, &unwrap).unwrap(),
+            "<a id=\"cover\"></a>![Cover](../images/cover.jpg)\n\n\
+             <svg viewBox=\"0 0 10 10\"><circle r=\"5\"></circle></svg>"
+        );
+    }
+
+    #[test]
+    fn test_ruby_annotations() {
+        let html = r#"<p>これは<ruby class="r">漢<rp>(</rp><rt>かん</rt><rp>)</rp>字<rp>(</rp><rt>じ</rt><rp>)</rp></ruby>です。</p>"#;
+        let parenthesized = ChapterContext {
+            ruby_mode: RubyMode::Parenthesized,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &ChapterContext::default()).unwrap(),
+            "これは<ruby>漢<rp>(</rp><rt>かん</rt><rp>)</rp>字<rp>(</rp><rt>じ</rt><rp>)</rp></ruby>です。"
+        );
+        assert_eq!(
+            convert_epub_html_to_md(html, &parenthesized).unwrap(),
+            "これは漢(かん)字(じ)です。"
+        );
+    }
+
+    #[test]
+    fn test_book_locale_from_language_and_spine() {
+        assert!(BookLocale::new(Some("ar-EG"), None, false).right_to_left);
+        assert!(BookLocale::new(Some("en"), Some("rtl"), false).right_to_left);
+        assert_eq!(
+            BookLocale::new(Some("ja"), Some("rtl"), false),
+            BookLocale {
+                language: Some("ja".to_string()),
+                right_to_left: false,
+                vertical_writing: true,
+            }
+        );
+        assert!(!BookLocale::new(Some("en"), Some("ltr"), false).right_to_left);
+    }
+
+    #[test]
+    fn test_dir_differing_from_book_is_preserved() {
+        let html = r#"<p>Hello <span dir="rtl">שלום</span></p><p dir="rtl">שלום עולם</p><p dir="ltr">Hi</p>"#;
+        let rtl = ChapterContext {
+            right_to_left: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &ChapterContext::default()).unwrap(),
+            "Hello <span dir=\"rtl\">שלום</span>\n\n<div dir=\"rtl\">\n\nשלום עולם\n\n</div>\n\nHi"
+        );
+        assert_eq!(
+            convert_epub_html_to_md(html, &rtl).unwrap(),
+            "Hello שלום\n\nשלום עולם\n\n<div dir=\"ltr\">\n\nHi\n\n</div>"
+        );
+    }
+
+    #[test]
+    fn test_passages_in_other_languages_keep_lang() {
+        let html = r#"<body xml:lang="en"><p>As Homer says, <i lang="grc">μῆνιν ἄειδε</i>.</p><blockquote lang="el"><p lang="el">Καλημέρα</p></blockquote><p lang="en-GB">Colour</p></body>"#;
+        let context = ChapterContext {
+            language: Some("en".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "As Homer says, <span lang=\"grc\">*μῆνιν ἄειδε*</span>.\n\n<div lang=\"el\">\n\n> Καλημέρα\n\n</div>\n\nColour"
+        );
+
+        let chapter = r#"<html lang="fr"><body><h1>Chapitre un</h1><p>Texte.</p></body></html>"#;
+        let markdown = convert_epub_html_to_md(chapter, &context).unwrap();
+
+        assert_eq!(
+            markdown,
+            "# Chapitre un\n\n<div lang=\"fr\">\n\nTexte.\n\n</div>"
+        );
+        assert_eq!(
+            add_missing_chapter_title(&markdown, Some("Chapitre un"), 1),
+            markdown
+        );
+    }
+
+    #[test]
+    fn test_definition_lists_and_inline_html() {
+        let html = r#"<dl><dt id="atom">Atom</dt><dd>Smallest unit of H<sub>2</sub>O</dd><dd>From <abbr title="Ancient Greek">Gr.</abbr> <em>atomos</em></dd><div><dt>Ion</dt><dd>Press <kbd>Ctrl</kbd><sup>1</sup></dd></div></dl><dl><dt>A</dt><dt>B</dt><dd>Both</dd></dl><p><span class="SmallCaps">ad</span> 1984</p>"#;
+        let context = ChapterContext {
+            small_caps: true,
+            referenced_ids: HashSet::from(["atom".to_string()]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "<a id=\"atom\"></a>Atom\n: Smallest unit of H<sub>2</sub>O\n: From <abbr title=\"Ancient Greek\">Gr.</abbr> *atomos*\n\nIon\n: Press <kbd>Ctrl</kbd><sup>1</sup>\n\n<dl>\n<dt>A</dt>\n<dt>B</dt>\n<dd>Both</dd>\n</dl>\n\n<span style=\"font-variant: small-caps\">ad</span> 1984"
+        );
+    }
+
+    #[test]
+    fn test_verse_keeps_lines_and_indentation() {
+        let html = r#"<div class="poem"><h3>Ozymandias</h3><div class="stanza"><p class="line">I met a traveller</p><p class="line indent2">Who said</p></div><div class="stanza"><span class="i0">Two vast<br/></span><span class="i1">legs <em>of stone</em><br/></span></div></div><p class="verse">One<br/>Two<br/><br/>Three</p>"#;
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &ChapterContext::default()).unwrap(),
+            "### Ozymandias\n\nI met a traveller  \n&nbsp;&nbsp;&nbsp;&nbsp;Who said\n\nTwo vast  \n&nbsp;&nbsp;legs *of stone*\n\nOne  \nTwo\n\nThree"
+        );
+    }
+
+    #[test]
+    fn test_figure_caption_follows_image() {
+        let html = r#"<figure id="fig-3-2"><figcaption>Figure 3.2: The <em>Tower</em></figcaption><div><img src="tower.png"/></div></figure><figure><img src="a.png" alt="A plan"/><figcaption>Plan</figcaption></figure>"#;
+        let context = ChapterContext {
+            referenced_ids: HashSet::from(["fig-3-2".to_string()]),
+            ..Default::default()
+        };
+        let html_captions = ChapterContext {
+            figcaption_html: true,
+            ..context.clone()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "<a id=\"fig-3-2\"></a>\n\n![Figure 3.2: The Tower](tower.png)\n\nFigure 3.2: The *Tower*\n\n![A plan](a.png)\n\nPlan"
+        );
+        assert_eq!(
+            convert_epub_html_to_md(html, &html_captions).unwrap(),
+            "<a id=\"fig-3-2\"></a>\n\n<figure>\n\n![Figure 3.2: The Tower](tower.png)\n\n<figcaption>\n\nFigure 3.2: The *Tower*\n\n</figcaption>\n\n</figure>\n\n<figure>\n\n![A plan](a.png)\n\n<figcaption>\n\nPlan\n\n</figcaption>\n\n</figure>"
+        );
+    }
+
+    #[test]
+    fn test_formatting_is_inferred_from_css() {
+        let mut stylesheet = Stylesheet::default();
+        stylesheet.add(
+            r#"
+            /* Calibre */
+            @import url(fonts.css);
+            .calibre5 { font-style: italic }
+            span.calibre12, .bold { font-weight: 700 }
+            .strike { text-decoration: line-through }
+            @media screen { .mono { font-family: "Courier New", monospace } }
+            p.center { text-align: center !important }
+            div .ignored { font-weight: bold }
+            "#,
+        );
+        let html = r#"<p class="calibre5">An <span class="calibre12">italic</span> paragraph</p><p>A <span class="bold"> bold </span><span class="strike">gone</span> <span class="mono">x = 1</span> <span class="mono">see <a href="https://example.com">docs</a></span> <em><span style="font-style: italic">twice</span></em></p><p class="center">The End</p>"#;
+        let context = ChapterContext {
+            stylesheet: Arc::new(stylesheet),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "*An **italic** paragraph*\n\nA **bold** ~~gone~~ `x = 1` see [docs](https://example.com) *twice*\n\n<div style=\"text-align: center\">\n\nThe End\n\n</div>"
+        );
+    }
+
+    #[test]
+    fn test_inferred_formatting_around_tags() {
+        let mut stylesheet = Stylesheet::default();
+        stylesheet.add(".italic { font-style: italic } .bold { font-weight: bold }");
+        let html = r#"<p><span class="italic">An <i>x</i></span> and <span class="bold">a <span class="bold">y</span></span> but <span class="italic">a <b>z</b></span></p>"#;
+        let context = ChapterContext {
+            stylesheet: Arc::new(stylesheet),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "*An *x** and **a y** but *a **z***"
+        );
+    }
+
+    #[test]
+    fn test_unreferenced_vendor_anchors_and_page_breaks_are_dropped() {
+        let html = r#"<h2 id="calibre_link-1">One</h2><div class="mbp_pagebreak"></div><p id="calibre_link-2">Two</p><p id="intro">Three</p><div id="calibre_pb_3" class="calibre_pb_3"></div>"#;
+        let context = ChapterContext {
+            referenced_ids: HashSet::from(["calibre_link-2".to_string()]),
+            keep_all_anchors: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "## One\n\n<a id=\"calibre_link-2\"></a>\n\nTwo\n\n<a id=\"intro\"></a>\n\nThree"
+        );
+    }
+
+    #[test]
+    fn test_unreferenced_ids_get_no_anchor_in_any_handler() {
+        let html = r#"<dl><dt id="term">Term</dt><dd id="def">Definition</dd></dl><table><tr><td id="cell">Cell</td></tr></table><figure><img src="a.png" alt="A"/><figcaption id="caption">Caption</figcaption></figure><svg id="drawing"><title>Drawing</title></svg><div class="poem"><p id="line">A line</p></div>"#;
+        let context = ChapterContext {
+            svg_mode: SvgMode::Extract,
+            svg_file_prefix: "ch1-svg".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "Term\n: Definition\n\n|  |\n| --- |\n| Cell |\n\n![A](a.png)\n\nCaption\n\n![Drawing](ch1-svg-1.svg)\n\nA line"
+        );
+    }
+
+    #[test]
+    fn test_vendor_ids_get_no_anchor_in_tables_and_definition_lists() {
+        let html = r#"<dl><dt id="calibre_link-3">Term</dt><dd id="meaning">Definition</dd></dl><table><tr><td id="calibre_link-12">A</td><td id="prices">B</td></tr></table><table><tr><td id="calibre_link-13" rowspan="2">C</td><td id="total">D</td></tr></table>"#;
+        let context = ChapterContext {
+            keep_all_anchors: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "Term\n: <a id=\"meaning\"></a>Definition\n\n\
+             |  |  |\n| --- | --- |\n| A | <a id=\"prices\"></a>B |\n\n\
+             <table><tbody>\n<tr><td rowspan=\"2\">C</td><td id=\"total\">D</td></tr></tbody>\n</table>"
+        );
+    }
+
+    #[test]
+    fn test_ids_on_inline_elements_and_list_items() {
+        let html = r##"<p>Water<sup id="ref1"><a href="#fn1">1</a></sup> is <em id="wet">wet</em>.</p><ul><li id="fn1">Mostly.</li><li>Else</li></ul><ol><li id="n2">One</li></ol>"##;
+        let context = ChapterContext {
+            keep_all_anchors: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "Water<a id=\"ref1\"></a><sup>[1](#fn1)</sup> is <a id=\"wet\"></a>*wet*.\n\n*   <a id=\"fn1\"></a>Mostly.\n*   Else\n\n1.  <a id=\"n2\"></a>One"
+        );
+    }
+
+    #[test]
+    fn test_ids_on_table_rows_captions_math_and_ruby() {
+        let html = r#"<table id="t1"><caption id="cap">Prices</caption><tr id="r1"><td>A</td><td>1</td></tr><tr id="r2"><td>B</td><td>2</td></tr></table><p>Read <ruby id="kanji">漢<rt>かん</rt></ruby> and <math id="eq1"><mi>x</mi></math></p><math id="eq2" display="block"><mi>y</mi></math>"#;
+        let context = ChapterContext {
+            keep_all_anchors: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "<a id=\"t1\"></a>\n\n<a id=\"cap\"></a>Prices\n\n\
+             |  |  |\n| --- | --- |\n| <a id=\"r1\"></a>A | 1 |\n| <a id=\"r2\"></a>B | 2 |\n\n\
+             Read <a id=\"kanji\"></a><ruby>漢<rt>かん</rt></ruby> and <a id=\"eq1\"></a>\\\\( x \\\\)\n\n\
+             <a id=\"eq2\"></a>\n\n\\\\[ y \\\\]"
+        );
+    }
+
+    #[test]
+    fn test_legacy_name_and_xml_id_become_anchors() {
+        let html = r#"<h2><a name="ch1"></a>Chapter One</h2><p xml:id="p1">Text with <a name="n1">a note</a>.</p>"#;
+        let context = ChapterContext {
+            referenced_ids: HashSet::from(["ch1".to_string(), "p1".to_string(), "n1".to_string()]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            convert_epub_html_to_md(html, &context).unwrap(),
+            "## <a id=\"ch1\"></a>Chapter One\n\n<a id=\"p1\"></a>\n\nText with <a id=\"n1\"></a>a note."
+        );
+    }
+
+    #[test]
+    fn test_links_to_missing_anchors_are_reported() {
+        let chapters = HashMap::from([
+            (
+                PathBuf::from("text/ch1.md"),
+                "# Chapter One\n\nSee [notes](notes.md#fn1), [here](#chapter-one),\n[gone](notes.md#fn9) and [web](https://example.com/#top).".to_string(),
+            ),
+            (
+                PathBuf::from("text/notes.md"),
+                "# <a id=\"top\"></a>Notes\n\n<a id=\"fn1\"></a>\n\nA note.\n\n[Back](#missing) [Up](#notes)\n\n<table>\n<tr><td rowspan=\"2\"><a href=\"ch1.md#nowhere\">x</a> <a href=\"#fn1\">y</a></td></tr>\n</table>".to_string(),
+            ),
+        ]);
+
+        assert_eq!(
+            check_fragment_links(&chapters),
+            vec![
+                Warning::MissingAnchor {
+                    chapter: "text/ch1.md".to_string(),
+                    line: 4,
+                    target: "notes.md#fn9".to_string(),
+                },
+                Warning::MissingAnchor {
+                    chapter: "text/notes.md".to_string(),
+                    line: 7,
+                    target: "#missing".to_string(),
+                },
+                Warning::MissingAnchor {
+                    chapter: "text/notes.md".to_string(),
+                    line: 10,
+                    target: "ch1.md#nowhere".to_string(),
+                },
+            ]
+        );
     }
 
     #[test]
@@ -606,8 +2612,8 @@ mod tests {
         "#;
         let title = "Chapter One".to_string();
 
-        let markdown = convert_epub_html_to_md(html).unwrap();
-        let markdown = add_missing_chapter_title(&markdown, Some(&title));
+        let markdown = convert_epub_html_to_md(html, &ChapterContext::default()).unwrap();
+        let markdown = add_missing_chapter_title(&markdown, Some(&title), 1);
 
         assert_eq!(markdown, "# Chapter One\n\nOpening paragraph.");
     }
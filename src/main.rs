@@ -1,7 +1,11 @@
 use std::path::PathBuf;
 
-use clap::Parser;
-use epub2mdbook::{convert_epub_to_mdbook, error::Error};
+use clap::{Parser, ValueEnum};
+use epub2mdbook::{
+    convert_epub_to_mdbook_with_options,
+    error::Error,
+    options::{AdmonitionStyle, BrokenLinkMode, ConvertOptions, RubyMode, SvgMode},
+};
 
 #[derive(Parser)]
 struct Args {
@@ -13,11 +17,164 @@ struct Args {
     /// Output directly to the output directory without creating a subdirectory named after the book
     #[clap(short, long)]
     flat: bool,
+    /// Turn top-level TOC entries that only hold a part title page into part headers
+    #[clap(long)]
+    part_headers: bool,
+    /// Shift the headings of each chapter so its top heading becomes `#`
+    #[clap(long)]
+    normalize_headings: bool,
+    /// With --normalize-headings, demote every top heading of a chapter after the first one, with its subheadings
+    #[clap(long, requires = "normalize_headings")]
+    demote_extra_h1: bool,
+    /// With --normalize-headings, move chapter headings one level deeper per TOC nesting level
+    #[clap(long, requires = "normalize_headings")]
+    toc_depth_heading_offset: bool,
+    /// Convert callouts such as `<div class="note">` to admonitions of this style
+    #[clap(long, value_enum)]
+    admonitions: Option<AdmonitionArg>,
+    /// Map a callout class to an admonition kind, e.g. `sidebar=info` (repeatable)
+    #[clap(long, value_name = "CLASS=KIND", value_parser = parse_mapping, requires = "admonitions")]
+    admonition_class: Vec<(String, String)>,
+    /// What to do with inline SVG elements
+    #[clap(long, value_enum, default_value = "inline")]
+    svg: SvgArg,
+    /// How ruby annotations (furigana, pinyin) are carried over
+    #[clap(long, value_enum, default_value = "html")]
+    ruby: RubyArg,
+    /// Render spans with a small-caps class such as `smallcaps` or `sc` in small caps
+    #[clap(long)]
+    small_caps: bool,
+    /// Treat this class as small caps too (repeatable)
+    #[clap(long, value_name = "CLASS", requires = "small_caps")]
+    small_caps_class: Vec<String>,
+    /// Write figure captions as `<figcaption>` HTML instead of a paragraph
+    #[clap(long)]
+    figcaption_html: bool,
+    /// Treat this class as a poem or stanza, keeping its line breaks (repeatable)
+    #[clap(long, value_name = "CLASS")]
+    verse_class: Vec<String>,
+    /// Keep an anchor for every element id, not only for those links point to
+    #[clap(long)]
+    keep_all_anchors: bool,
+    /// Add a stylesheet that sets the text in vertical lines when the EPUB does
+    #[clap(long)]
+    vertical_writing_css: bool,
+    /// What to do with links to chapters or files the EPUB doesn't have
+    #[clap(long, value_enum, default_value = "keep")]
+    broken_links: BrokenLinksArg,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AdmonitionArg {
+    /// Fenced blocks for the mdbook-admonish preprocessor
+    Admonish,
+    /// GitHub-style `> [!NOTE]` alerts
+    Github,
+}
+
+impl From<AdmonitionArg> for AdmonitionStyle {
+    fn from(arg: AdmonitionArg) -> Self {
+        match arg {
+            AdmonitionArg::Admonish => AdmonitionStyle::Admonish,
+            AdmonitionArg::Github => AdmonitionStyle::GitHub,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SvgArg {
+    /// Keep the SVG as raw HTML in the chapter
+    Inline,
+    /// Write the SVG to a standalone .svg file referenced as an image
+    Extract,
+    /// Replace SVGs wrapping a raster image, like most covers, with that image
+    Unwrap,
+}
+
+impl From<SvgArg> for SvgMode {
+    fn from(arg: SvgArg) -> Self {
+        match arg {
+            SvgArg::Inline => SvgMode::Inline,
+            SvgArg::Extract => SvgMode::Extract,
+            SvgArg::Unwrap => SvgMode::Unwrap,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum BrokenLinksArg {
+    /// Leave the link as is
+    Keep,
+    /// Replace the link with its text
+    Unlink,
+    /// Point the link to the only chapter with the same file name, if any
+    Fuzzy,
+}
+
+impl From<BrokenLinksArg> for BrokenLinkMode {
+    fn from(arg: BrokenLinksArg) -> Self {
+        match arg {
+            BrokenLinksArg::Keep => BrokenLinkMode::Keep,
+            BrokenLinksArg::Unlink => BrokenLinkMode::Unlink,
+            BrokenLinksArg::Fuzzy => BrokenLinkMode::Fuzzy,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RubyArg {
+    /// Keep ruby as raw inline HTML, which mdBook renders
+    Html,
+    /// Write the reading in parentheses after its base text, e.g. 漢字(かんじ)
+    Parenthesized,
+}
+
+impl From<RubyArg> for RubyMode {
+    fn from(arg: RubyArg) -> Self {
+        match arg {
+            RubyArg::Html => RubyMode::Html,
+            RubyArg::Parenthesized => RubyMode::Parenthesized,
+        }
+    }
+}
+
+fn parse_mapping(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !value.is_empty() => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got `{value}`")),
+    }
 }
 
 fn main() -> Result<(), Error> {
     let args = Args::parse();
-    convert_epub_to_mdbook(args.input_epub, args.output_dir, !args.flat)?;
+    let options = ConvertOptions {
+        part_headers: args.part_headers,
+        normalize_headings: args.normalize_headings,
+        demote_extra_h1: args.demote_extra_h1,
+        toc_depth_heading_offset: args.toc_depth_heading_offset,
+        admonition_style: args.admonitions.map(Into::into),
+        admonition_classes: args.admonition_class.into_iter().collect(),
+        svg_mode: args.svg.into(),
+        ruby_mode: args.ruby.into(),
+        small_caps: args.small_caps,
+        small_caps_classes: args.small_caps_class,
+        figcaption_html: args.figcaption_html,
+        verse_classes: args.verse_class,
+        keep_all_anchors: args.keep_all_anchors,
+        vertical_writing_css: args.vertical_writing_css,
+        broken_links: args.broken_links.into(),
+    };
+    let report = convert_epub_to_mdbook_with_options(
+        args.input_epub,
+        args.output_dir,
+        !args.flat,
+        &options,
+    )?;
+    for warning in &report.warnings {
+        eprintln!("warning: {warning}");
+    }
     println!("Conversion completed successfully!");
     Ok(())
 }
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+/// Options controlling how an EPUB file is converted to MDBook format
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Turn top-level TOC entries that only point to a part title page (or have no
+    /// content of their own) into MDBook part headers, with their children as
+    /// top-level chapters.
+    pub part_headers: bool,
+    /// Shift the headings of each chapter so its top heading becomes `#`, with
+    /// nested levels following.
+    pub normalize_headings: bool,
+    /// With `normalize_headings`, demote every top heading of a chapter after the
+    /// first one, and the headings under it, by one level.
+    pub demote_extra_h1: bool,
+    /// With `normalize_headings`, move the top heading of each chapter one level
+    /// deeper for every level it is nested in the TOC.
+    pub toc_depth_heading_offset: bool,
+    /// Convert callouts such as `<div class="note">` or `<aside data-type="warning">`
+    /// to admonitions of this style. `None` keeps them as plain paragraphs.
+    pub admonition_style: Option<AdmonitionStyle>,
+    /// Extra `class → admonition kind` mappings, on top of the built-in ones for
+    /// `note`, `tip`, `warning` and friends. Classes match case-insensitively.
+    pub admonition_classes: HashMap<String, String>,
+    /// What to do with inline `<svg>` elements
+    pub svg_mode: SvgMode,
+    /// How `<ruby>` annotations (furigana, pinyin) are carried over
+    pub ruby_mode: RubyMode,
+    /// Render spans with a small-caps class, such as `smallcaps` or `sc`, as
+    /// `<span style="font-variant: small-caps">`.
+    pub small_caps: bool,
+    /// Extra small-caps classes, on top of the built-in ones. Classes match
+    /// case-insensitively.
+    pub small_caps_classes: Vec<String>,
+    /// Write figure captions as `<figcaption>` in a raw HTML `<figure>` instead of
+    /// a plain paragraph below the image.
+    pub figcaption_html: bool,
+    /// Extra classes marking poems and stanzas, on top of the built-in `poem`,
+    /// `verse`, `stanza` and friends. Classes match case-insensitively.
+    pub verse_classes: Vec<String>,
+    /// Keep an `<a id>` anchor for every element with an id, instead of only for
+    /// those some link or TOC entry points to. Unreferenced Calibre and InDesign
+    /// ids are dropped either way.
+    pub keep_all_anchors: bool,
+    /// Write a `vertical-writing.css` and add it to book.toml when the book is set
+    /// in vertical lines, as declared by its stylesheets or a right-to-left spine
+    /// in Chinese, Japanese or Korean.
+    pub vertical_writing_css: bool,
+    /// What to do with links to chapters or files the book doesn't have. They are
+    /// reported either way.
+    pub broken_links: BrokenLinkMode,
+}
+
+/// How callouts are written in the Markdown output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdmonitionStyle {
+    /// Fenced ```` ```admonish note ```` blocks for the `mdbook-admonish`
+    /// preprocessor, which gets registered in book.toml
+    Admonish,
+    /// GitHub-style `> [!NOTE]` alerts
+    GitHub,
+}
+
+/// How inline `<svg>` elements are carried over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SvgMode {
+    /// Keep the SVG as raw HTML in the chapter
+    #[default]
+    Inline,
+    /// Write the SVG to a standalone `.svg` file next to the chapter and reference
+    /// it as an image
+    Extract,
+    /// Replace an SVG that wraps a raster `<image>`, like most covers do, with that
+    /// image. SVGs without one are kept inline.
+    Unwrap,
+}
+
+/// What is done with links to chapters or files the book doesn't have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BrokenLinkMode {
+    /// Leave the link pointing to the missing file
+    #[default]
+    Keep,
+    /// Replace the link with its text
+    Unlink,
+    /// Point the link to the chapter with the same file stem, like `ch01.html` for
+    /// a missing `Text/ch01.xhtml`, if there is exactly one
+    Fuzzy,
+}
+
+/// How `<ruby>` annotations are carried over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RubyMode {
+    /// Keep `<ruby>`/`<rt>`/`<rp>` as raw inline HTML, which mdBook renders
+    #[default]
+    Html,
+    /// Write the reading in parentheses after its base text, e.g. `漢字(かんじ)`
+    Parenthesized,
+}
@@ -0,0 +1,63 @@
+use thiserror::Error;
+
+/// Issues found during a conversion that did not stop it
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Warnings in the order they were found
+    pub warnings: Vec<Warning>,
+}
+
+/// A problem with the EPUB that the conversion worked around
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// A TOC entry points to a file the EPUB doesn't have, but has children. It
+    /// becomes a draft chapter in SUMMARY.md so its children stay nested.
+    #[error("TOC entry \"{label}\" points to missing {target}, emitted as a draft chapter")]
+    DraftChapter { label: String, target: String },
+
+    /// A TOC entry without children points to a file the EPUB doesn't have, and is
+    /// left out of SUMMARY.md.
+    #[error("TOC entry \"{label}\" points to missing {target}, dropped")]
+    DroppedTocEntry { label: String, target: String },
+
+    /// A link between chapters has a fragment that matches neither an anchor nor a
+    /// heading id of the target chapter. `line` is 1-based, in the Markdown output.
+    #[error("{chapter}:{line}: link to {target} has no matching anchor")]
+    MissingAnchor {
+        chapter: String,
+        line: usize,
+        target: String,
+    },
+
+    /// A link points to a chapter or file the EPUB doesn't have, and was kept as is
+    /// with [`BrokenLinkMode::Keep`](crate::options::BrokenLinkMode::Keep) or when
+    /// [`BrokenLinkMode::Fuzzy`](crate::options::BrokenLinkMode::Fuzzy) found no
+    /// chapter with the same file stem.
+    #[error("{chapter}:{line}: link to missing {target}")]
+    BrokenLink {
+        chapter: String,
+        line: usize,
+        target: String,
+    },
+
+    /// A link points to a chapter or file the EPUB doesn't have, and was replaced
+    /// with its text as asked by
+    /// [`BrokenLinkMode::Unlink`](crate::options::BrokenLinkMode::Unlink).
+    #[error("{chapter}:{line}: link to missing {target}, unlinked")]
+    UnlinkedBrokenLink {
+        chapter: String,
+        line: usize,
+        target: String,
+    },
+
+    /// A link points to a chapter or file the EPUB doesn't have, and was pointed to
+    /// `resolved`, the only chapter with the same file stem, as asked by
+    /// [`BrokenLinkMode::Fuzzy`](crate::options::BrokenLinkMode::Fuzzy).
+    #[error("{chapter}:{line}: link to missing {target}, pointed to {resolved} instead")]
+    ResolvedBrokenLink {
+        chapter: String,
+        line: usize,
+        target: String,
+        resolved: String,
+    },
+}